@@ -21,18 +21,10 @@ parse_yaml_file() {
   # Extract the section for the given app name from the JSON output
   local section=$(echo "$json_output" | jq -r ".$config_name")
 
-  # Extract the keys and values from the section
-  local keys=$(echo "$section" | jq -r 'keys_unsorted[]')
-  local values=$(echo "$section" | jq -r '.[]')
-  local values_array=($values)
-
-  # Process each key-value pair
-  ITER=0
-  for key in $keys
+  # Process each key-value pair, one per line so values can contain spaces (e.g. cron expressions)
+  echo "$section" | jq -r 'to_entries[] | "\(.key)\t\(.value)"' | while IFS=$'\t' read -r key value
   do
-    local value=${values_array[$ITER]}
     process_yaml_key "$key" "$value"
-    ITER=$(expr $ITER + 1)
   done
 }
 
@@ -45,7 +37,7 @@ execute_cli() {
   # # Modify this command to run your actual CLI
   # # Currently, it just echoes the CLI path and arguments
   echo "Executing: $cli_path $@"
-  $cli_path $@
+  "$cli_path" "$@"
 }
 
 # Usage: ./yaml_to_cli.sh <input_file.yaml>
@@ -68,7 +60,7 @@ fi
 
 
 # Parse the YAML file and convert keys to CLI arguments for the given app name
-arguments=$(parse_yaml_file "$1" "$CONFIG_NAME")
+mapfile -t arguments < <(parse_yaml_file "$1" "$CONFIG_NAME")
 
 # Execute the CLI with the parsed arguments
-execute_cli "$APP_NAME" $arguments
+execute_cli "$APP_NAME" "${arguments[@]}"
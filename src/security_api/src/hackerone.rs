@@ -68,6 +68,7 @@ pub async fn fetch_csrf_token(session_token: &str) -> Result<String, anyhow::Err
 
 // GraphQL types
 type DateTime = String;
+#[allow(clippy::upper_case_acronyms)]
 type URI = String;
 
 #[derive(GraphQLQuery, Debug)]
@@ -119,6 +120,68 @@ pub struct DiscoveryQuery;
 )]
 pub struct UserProfileThanks;
 
+#[derive(GraphQLQuery, Debug)]
+#[graphql(
+    schema_path = "../../graphql/schema.graphql",
+    query_path = "../../graphql/TeamStructuredScopesQuery.graphql",
+    variables_derives = "Default, PartialEq",
+    response_derives = "Debug, PartialEq",
+    skip_serializing_none
+)]
+pub struct TeamStructuredScopesQuery;
+
+#[derive(GraphQLQuery, Debug)]
+#[graphql(
+    schema_path = "../../graphql/schema.graphql",
+    query_path = "../../graphql/TeamBountyTableQuery.graphql",
+    variables_derives = "Default, PartialEq",
+    response_derives = "Debug, PartialEq",
+    skip_serializing_none
+)]
+pub struct TeamBountyTableQuery;
+
+#[derive(GraphQLQuery, Debug)]
+#[graphql(
+    schema_path = "../../graphql/schema.graphql",
+    query_path = "../../graphql/TeamPolicyQuery.graphql",
+    variables_derives = "Default, PartialEq",
+    response_derives = "Debug, PartialEq",
+    skip_serializing_none
+)]
+pub struct TeamPolicyQuery;
+
+#[derive(GraphQLQuery, Debug)]
+#[graphql(
+    schema_path = "../../graphql/schema.graphql",
+    query_path = "../../graphql/CurrentUserQuery.graphql",
+    variables_derives = "Default, PartialEq",
+    response_derives = "Debug, PartialEq",
+    skip_serializing_none
+)]
+pub struct CurrentUserQuery;
+
+/// Whether the session token is still signed in, without a session token there is nothing
+/// to expire
+pub async fn is_session_valid(client: &HackerOneClient) -> Result<bool, anyhow::Error> {
+    if client.session_token.as_deref().unwrap_or_default().is_empty() {
+        return Ok(true);
+    }
+
+    let query = CurrentUserQuery::build_query(current_user_query::Variables {});
+    let response = client
+        .http
+        .post("https://hackerone.com/graphql")
+        .json(&query)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let data = response
+        .json::<graphql_client::Response<<CurrentUserQuery as GraphQLQuery>::ResponseData>>()
+        .await?;
+    Ok(data.data.is_some_and(|data| data.me.is_some()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,198 @@
+//! Liveness and readiness endpoints. Long running tasks register a heartbeat and beat it
+//! whenever they make progress, `/healthz` fails once any of them stops beating.
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use deadpool_redis::redis;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::redis as kv;
+
+static HEARTBEATS: LazyLock<RwLock<BTreeMap<String, Heartbeat>>> =
+    LazyLock::new(|| RwLock::new(BTreeMap::new()));
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    /// `None` until the task made progress for the first time
+    pub last_beat: Option<DateTime<Utc>>,
+    pub registered_at: DateTime<Utc>,
+    /// The task is idle on purpose until then, it can't go stale before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    /// How long the task may go without a beat
+    #[serde(serialize_with = "serialize_seconds")]
+    pub stale_after: Duration,
+}
+
+impl Heartbeat {
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        let since = self.last_beat.unwrap_or(self.registered_at);
+        let since = self.snoozed_until.map_or(since, |until| until.max(since));
+        now.signed_duration_since(since)
+            .to_std()
+            .is_ok_and(|elapsed| elapsed > self.stale_after)
+    }
+}
+
+fn serialize_seconds<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_secs())
+}
+
+pub async fn register(name: &str, stale_after: Duration) {
+    let heartbeat = Heartbeat {
+        last_beat: None,
+        registered_at: Utc::now(),
+        snoozed_until: None,
+        stale_after,
+    };
+
+    HEARTBEATS
+        .write()
+        .await
+        .insert(String::from(name), heartbeat);
+}
+
+pub async fn beat(name: &str) {
+    if let Some(heartbeat) = HEARTBEATS.write().await.get_mut(name) {
+        heartbeat.last_beat = Some(Utc::now());
+    }
+}
+
+/// Keeps a task that is idle on purpose, e.g. waiting out a quiet period, from going stale
+/// before `until`
+pub async fn snooze(name: &str, until: DateTime<Utc>) {
+    if let Some(heartbeat) = HEARTBEATS.write().await.get_mut(name) {
+        heartbeat.snoozed_until = Some(until);
+    }
+}
+
+/// The result of one readiness check
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    pub fn new(name: &str, result: Result<(), anyhow::Error>) -> Self {
+        Check {
+            name: String::from(name),
+            ok: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+        }
+    }
+}
+
+pub async fn check_redis() -> Check {
+    let result = async {
+        let mut connection = kv::get_connection().get().await?;
+        redis::cmd("PING")
+            .query_async::<()>(&mut connection)
+            .await?;
+        Ok(())
+    };
+
+    Check::new("redis", result.await)
+}
+
+#[derive(Serialize, Debug)]
+struct Health {
+    ok: bool,
+    heartbeats: BTreeMap<String, HeartbeatStatus>,
+}
+
+#[derive(Serialize, Debug)]
+struct HeartbeatStatus {
+    #[serde(flatten)]
+    heartbeat: Heartbeat,
+    stale: bool,
+}
+
+async fn healthz() -> (StatusCode, Json<Health>) {
+    let now = Utc::now();
+    let heartbeats: BTreeMap<String, HeartbeatStatus> = HEARTBEATS
+        .read()
+        .await
+        .iter()
+        .map(|(name, heartbeat)| {
+            let status = HeartbeatStatus {
+                stale: heartbeat.is_stale(now),
+                heartbeat: heartbeat.clone(),
+            };
+
+            (name.clone(), status)
+        })
+        .collect();
+
+    let ok = heartbeats.values().all(|status| !status.stale);
+    (status_code(ok), Json(Health { ok, heartbeats }))
+}
+
+#[derive(Serialize, Debug)]
+struct Readiness {
+    ok: bool,
+    checks: Vec<Check>,
+}
+
+fn status_code(ok: bool) -> StatusCode {
+    if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// `/healthz` reports the heartbeats, `/readyz` runs `checks`
+pub fn router<F, Fut>(checks: F) -> Router
+where
+    F: Fn() -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Vec<Check>> + Send,
+{
+    let readyz = move || async move {
+        let checks = checks().await;
+        let ok = checks.iter().all(|check| check.ok);
+        (status_code(ok), Json(Readiness { ok, checks }))
+    };
+
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeats_go_stale() {
+        let registered_at = Utc::now();
+        let mut heartbeat = Heartbeat {
+            last_beat: None,
+            registered_at,
+            snoozed_until: None,
+            stale_after: Duration::from_secs(60),
+        };
+
+        assert!(!heartbeat.is_stale(registered_at + chrono::Duration::seconds(30)));
+        assert!(heartbeat.is_stale(registered_at + chrono::Duration::seconds(90)));
+
+        heartbeat.last_beat = Some(registered_at + chrono::Duration::seconds(60));
+        assert!(!heartbeat.is_stale(registered_at + chrono::Duration::seconds(90)));
+
+        heartbeat.snoozed_until = Some(registered_at + chrono::Duration::seconds(600));
+        assert!(!heartbeat.is_stale(registered_at + chrono::Duration::seconds(630)));
+        assert!(heartbeat.is_stale(registered_at + chrono::Duration::seconds(690)));
+    }
+}
@@ -1,3 +1,6 @@
 pub mod hackerone;
+pub mod health;
+pub mod metrics;
 pub mod models;
-pub mod redis;
\ No newline at end of file
+pub mod redis;
+pub mod reputation_history;
@@ -0,0 +1,56 @@
+//! Serves the metrics every binary registers in the default Prometheus registry
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{Encoder, TextEncoder};
+
+/// All registered metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        log::error!("failed to encode metrics: {}", err);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())], render())
+}
+
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(metrics))
+}
+
+/// Serves `router` until the process exits, a failure to bind is logged instead of taking
+/// the binary down with it
+pub async fn serve(listen: &str, router: Router) {
+    let listener = match tokio::net::TcpListener::bind(listen).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("failed to listen on {}: {}", listen, err);
+            return;
+        }
+    };
+
+    log::info!("serving metrics on {}", listen);
+    if let Err(err) = axum::serve(listener, router).await {
+        log::error!("metrics server failed: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_registered_metrics() {
+        let counter =
+            prometheus::register_int_counter!("test_renders_total", "Test counter").unwrap();
+        counter.inc_by(3);
+
+        assert!(render().contains("test_renders_total 3"));
+    }
+}
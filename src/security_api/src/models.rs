@@ -29,9 +29,22 @@ impl RepDataQueueItem {
     }
 }
 
+/// A researcher's reputation in a program after it changed, see [`crate::reputation_history`]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReputationPoint {
+    pub reputation: i64,
+    pub rank: i64,
+    /// `-1` if the researcher wasn't on the leaderboard before
+    pub previous_reputation: i64,
+
+    #[serde(with = "my_date_format")]
+    pub recorded_at: NaiveDateTime,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct ReportData {
     pub user_name: String,
+    /// Database id of the reporter, the same id [`RepData::user_id`] holds
     pub user_id: String,
 
     pub currency: String,
@@ -45,6 +58,88 @@ pub struct ReportData {
     pub severity: Option<String>,
     pub collaboration: bool,
     pub disclosed: bool,
+
+    #[serde(default)]
+    pub team_handle: Option<String>,
+
+    /// `None` for reports saved before these fields were tracked, so they aren't reported
+    /// as changed
+    #[serde(default)]
+    pub substate: Option<String>,
+    #[serde(default)]
+    pub cve_ids: Option<Vec<String>>,
+    #[serde(default)]
+    pub votes: Option<i64>,
+
+    /// The weakness, e.g. "Cross-site Scripting (XSS) - Stored"
+    #[serde(default)]
+    pub cwe: Option<String>,
+    #[serde(default)]
+    pub submitted_at: Option<String>,
+    #[serde(default)]
+    pub disclosed_at: Option<String>,
+    #[serde(default)]
+    pub team_name: Option<String>,
+    #[serde(default)]
+    pub team_profile_picture_url: Option<String>,
+}
+
+/// A change worth announcing between two snapshots of a report
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReportChange {
+    Disclosed,
+    /// Awarded amounts, `-1` if the amount was hidden
+    BountyAwarded { old: f64, new: f64 },
+    SeverityRerated { old: String, new: String },
+    CveAssigned(Vec<String>),
+    SubstateChanged { old: String, new: String },
+}
+
+impl ReportData {
+    /// Changes from `self` to `new`. Vote counts are tracked but not reported since they
+    /// change on nearly every poll.
+    pub fn changes(&self, new: &ReportData) -> Vec<ReportChange> {
+        let mut changes = vec![];
+        if !self.disclosed && new.disclosed {
+            // everything else is part of the disclosure
+            changes.push(ReportChange::Disclosed);
+            return changes;
+        }
+
+        if new.awarded_amount > 0.0 && new.awarded_amount > self.awarded_amount {
+            changes.push(ReportChange::BountyAwarded {
+                old: self.awarded_amount,
+                new: new.awarded_amount,
+            });
+        }
+
+        if let (Some(old), Some(new)) = (&self.severity, &new.severity) {
+            if old != new {
+                changes.push(ReportChange::SeverityRerated {
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+
+        if let (Some(old), Some(new)) = (&self.cve_ids, &new.cve_ids) {
+            let assigned: Vec<String> = new.iter().filter(|id| !old.contains(id)).cloned().collect();
+            if !assigned.is_empty() {
+                changes.push(ReportChange::CveAssigned(assigned));
+            }
+        }
+
+        if let (Some(old), Some(new)) = (&self.substate, &new.substate) {
+            if old != new {
+                changes.push(ReportChange::SubstateChanged {
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+
+        changes
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -85,10 +180,186 @@ pub struct UserInvalidReportChange {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InvalidReportsQueueItem {
+    #[serde(default)]
+    pub id: Option<String>,
     pub changes: Vec<UserInvalidReportChange>,
     pub team_handle: String,
 }
 
+impl InvalidReportsQueueItem {
+    pub fn create_id(&mut self) {
+        let id = nanoid!();
+        self.id = Some(id);
+    }
+}
+
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ProgramData {
+    pub handle: String,
+    pub name: String,
+    pub profile_picture_url: String,
+    pub offers_bounties: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProgramsQueueItem {
+    pub id: Option<String>,
+    pub launched: Vec<ProgramData>,
+    pub retired: Vec<ProgramData>,
+
+    #[serde(with = "my_date_format")]
+    pub created_at: NaiveDateTime,
+}
+
+impl ProgramsQueueItem {
+    pub fn create_id(&mut self) {
+        let id = nanoid!();
+        self.id = Some(id);
+    }
+}
+
+/// An asset in a program's structured scope, assets not eligible for submission are out of scope
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ScopeData {
+    pub id: String,
+    pub team_handle: String,
+    pub asset_identifier: String,
+    pub asset_type: Option<String>,
+    pub eligible_for_submission: bool,
+    pub eligible_for_bounty: bool,
+    pub max_severity: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScopeDataQueueItem {
+    pub id: Option<String>,
+    pub team_handle: String,
+    /// `[old, new]` pairs, an empty old or new asset (without id) means it was added or removed
+    pub diff: Vec<Vec<ScopeData>>,
+
+    #[serde(with = "my_date_format")]
+    pub created_at: NaiveDateTime,
+}
+
+impl ScopeDataQueueItem {
+    pub fn create_id(&mut self) {
+        let id = nanoid!();
+        self.id = Some(id);
+    }
+}
+
+/// A row of a program's bounty table, amounts are `None` if the program doesn't pay for a
+/// severity. Programs using ranges also set the minimums.
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BountyTableRowData {
+    pub id: String,
+    /// The asset this row applies to, rows without one apply to every asset
+    pub asset_identifier: Option<String>,
+    pub low: Option<i64>,
+    pub low_minimum: Option<i64>,
+    pub medium: Option<i64>,
+    pub medium_minimum: Option<i64>,
+    pub high: Option<i64>,
+    pub high_minimum: Option<i64>,
+    pub critical: Option<i64>,
+    pub critical_minimum: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BountyTableQueueItem {
+    pub id: Option<String>,
+    pub team_handle: String,
+    pub currency: String,
+    /// `[old, new]` pairs, an empty old or new row (without id) means it was added or removed
+    pub diff: Vec<Vec<BountyTableRowData>>,
+
+    #[serde(with = "my_date_format")]
+    pub created_at: NaiveDateTime,
+}
+
+impl BountyTableQueueItem {
+    pub fn create_id(&mut self) {
+        let id = nanoid!();
+        self.id = Some(id);
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PolicyQueueItem {
+    pub id: Option<String>,
+    pub team_handle: String,
+    /// SHA-256 of the policy text before and after the edit
+    pub old_hash: String,
+    pub new_hash: String,
+    /// Unified diff of the policy text
+    pub diff: String,
+
+    #[serde(with = "my_date_format")]
+    pub created_at: NaiveDateTime,
+}
+
+impl PolicyQueueItem {
+    pub fn create_id(&mut self) {
+        let id = nanoid!();
+        self.id = Some(id);
+    }
+}
+
+/// Whether a poll kept failing or works again, see `security_poller::scheduler`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PollStatus {
+    Degraded,
+    Recovered,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PollStatusQueueItem {
+    pub id: Option<String>,
+    pub poll: String,
+    pub status: PollStatus,
+    /// Failures in a row before the poll was degraded or recovered
+    pub consecutive_failures: u32,
+    /// The last error, only set for degraded polls
+    pub error: Option<String>,
+
+    #[serde(with = "my_date_format")]
+    pub created_at: NaiveDateTime,
+}
+
+impl PollStatusQueueItem {
+    pub fn create_id(&mut self) {
+        let id = nanoid!();
+        self.id = Some(id);
+    }
+}
+
+/// A queue entry the notifier couldn't deliver because a receiver refused it for good
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeadLetter {
+    /// The stream and id of the entry
+    pub stream: String,
+    pub entry_id: String,
+    pub error: String,
+    /// The queue item as it was queued
+    pub payload: String,
+
+    #[serde(with = "my_date_format")]
+    pub created_at: NaiveDateTime,
+}
+
+impl DeadLetter {
+    pub fn new(stream: &str, entry_id: &str, error: &str, payload: &str) -> Self {
+        DeadLetter {
+            stream: String::from(stream),
+            entry_id: String::from(entry_id),
+            error: String::from(error),
+            payload: String::from(payload),
+            created_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
 pub mod embed_colors {
     pub const NEGATIVE: u32 = 16711680;
     pub const POSTIVE: u32 = 5222492;
@@ -98,18 +369,112 @@ pub mod embed_colors {
 }
 
 pub mod redis_keys {
-    pub const REPUTATION_QUEUE_BACKLOG: &str = "reputation_queue";
-    pub const REPUTATION_QUEUE_PUBSUB: &str = "reputation_poll_queue";
+    pub const REPUTATION_QUEUE_STREAM: &str = "reputation_poll_stream";
     pub const REPUTATION_QUEUE_LAST_DATA: &str = "reputation_poll_last_data";
 
-    pub const REPORTS_QUEUE_PUBSUB: &str = "reports_poll_queue";
+    /// Sorted sets of [`super::ReputationPoint`] per program and researcher, see
+    /// [`crate::reputation_history`]
+    pub const REPUTATION_HISTORY: &str = "reputation_history";
+    /// Set of the programs a researcher has a [`REPUTATION_HISTORY`] in, per researcher id
+    pub const REPUTATION_HISTORY_PROGRAMS: &str = "reputation_history_programs";
+
+    pub const REPORTS_QUEUE_STREAM: &str = "reports_poll_stream";
     pub const REPORTS_POLL_LAST_RUN_TIME: &str = "reports_poll_last_run_time";
+    /// Set the reports were saved in before [`REPORTS_POLL_STATE`], migrated on the next poll
     pub const REPORTS_POLL_LAST_DATA: &str = "reports_poll_last_data";
-    
+    /// Hash of report id to the latest data of the report
+    pub const REPORTS_POLL_STATE: &str = "reports_poll_state";
+    /// Sorted set of report ids scored by when the report was last fetched, used for pruning
+    pub const REPORTS_POLL_SEEN: &str = "reports_poll_seen";
+
     pub const PROGRAMS: &str = "programs";
+    pub const PROGRAMS_QUEUE_STREAM: &str = "programs_poll_stream";
+    pub const PROGRAMS_POLL_LAST_DATA: &str = "programs_poll_last_data";
 
-    pub const USER_INFORMATIVE_REPORTS_QUEUE_PUBSUB: &str = "user_informative_reports_queue";
+    pub const STRUCTURED_SCOPES_QUEUE_STREAM: &str = "structured_scopes_poll_stream";
+    pub const STRUCTURED_SCOPES_POLL_LAST_DATA: &str = "structured_scopes_poll_last_data";
+
+    pub const BOUNTY_TABLES_QUEUE_STREAM: &str = "bounty_tables_poll_stream";
+    pub const BOUNTY_TABLES_POLL_LAST_DATA: &str = "bounty_tables_poll_last_data";
+
+    pub const POLICY_QUEUE_STREAM: &str = "policy_poll_stream";
+    /// Hash with the `hash` and full `text` of a team's policy
+    pub const POLICY_POLL_LAST_DATA: &str = "policy_poll_last_data";
+
+    pub const USER_INFORMATIVE_REPORTS_QUEUE_STREAM: &str = "user_informative_reports_stream";
     pub const USER_THANKS_DATA_POLL_LAST_DATA: &str = "user_program_thanks_data_poll_last_data";
+
+    /// Set of the snapshots (e.g. `structured_scopes_poll_last_data:security`) that have
+    /// been saved, to tell an empty snapshot from a first run
+    pub const SNAPSHOTS_SAVED: &str = "snapshots_saved";
+
+    /// Hash of poll name to when the poll last ran successfully (milliseconds since epoch)
+    pub const POLL_LAST_SUCCESS_TIME: &str = "poll_last_success_time";
+    pub const POLL_STATUS_QUEUE_STREAM: &str = "poll_status_stream";
+
+    /// Stream of [`super::DeadLetter`]s, queue entries a receiver refused for good
+    pub const NOTIFIER_DEAD_LETTER_STREAM: &str = "notifier_dead_letter_stream";
+
+    /// Consumer group `security_discord` reads every queue stream with
+    pub const NOTIFIER_CONSUMER_GROUP: &str = "security_discord";
+
+    /// Every stream queue items are published to
+    pub const QUEUE_STREAMS: &[&str] = &[
+        REPUTATION_QUEUE_STREAM,
+        REPORTS_QUEUE_STREAM,
+        PROGRAMS_QUEUE_STREAM,
+        STRUCTURED_SCOPES_QUEUE_STREAM,
+        BOUNTY_TABLES_QUEUE_STREAM,
+        POLICY_QUEUE_STREAM,
+        USER_INFORMATIVE_REPORTS_QUEUE_STREAM,
+        POLL_STATUS_QUEUE_STREAM,
+    ];
+
+    /// Keys older versions kept the state of a single tracked team in without scoping them
+    /// to it, see [`for_team`]
+    pub const UNSCOPED_TEAM_KEYS: &[&str] = &[
+        REPUTATION_QUEUE_LAST_DATA,
+        REPORTS_POLL_LAST_RUN_TIME,
+        REPORTS_POLL_LAST_DATA,
+        USER_THANKS_DATA_POLL_LAST_DATA,
+    ];
+
+    /// Every key holding persistent state, used when migrating to a namespace
+    pub const ALL: &[&str] = &[
+        REPUTATION_QUEUE_STREAM,
+        REPUTATION_QUEUE_LAST_DATA,
+        REPUTATION_HISTORY,
+        REPUTATION_HISTORY_PROGRAMS,
+        REPORTS_QUEUE_STREAM,
+        REPORTS_POLL_LAST_RUN_TIME,
+        REPORTS_POLL_LAST_DATA,
+        REPORTS_POLL_STATE,
+        REPORTS_POLL_SEEN,
+        PROGRAMS,
+        PROGRAMS_QUEUE_STREAM,
+        PROGRAMS_POLL_LAST_DATA,
+        STRUCTURED_SCOPES_QUEUE_STREAM,
+        STRUCTURED_SCOPES_POLL_LAST_DATA,
+        BOUNTY_TABLES_QUEUE_STREAM,
+        BOUNTY_TABLES_POLL_LAST_DATA,
+        POLICY_QUEUE_STREAM,
+        POLICY_POLL_LAST_DATA,
+        USER_INFORMATIVE_REPORTS_QUEUE_STREAM,
+        USER_THANKS_DATA_POLL_LAST_DATA,
+        SNAPSHOTS_SAVED,
+        POLL_LAST_SUCCESS_TIME,
+        POLL_STATUS_QUEUE_STREAM,
+        NOTIFIER_DEAD_LETTER_STREAM,
+    ];
+
+    /// Scopes a key to a single team handle so every tracked program keeps its own state.
+    /// Without a handle (tracking all programs) the key is returned unchanged.
+    pub fn for_team(key: &str, team_handle: Option<&str>) -> String {
+        match team_handle {
+            Some(team_handle) => format!("{key}:{team_handle}"),
+            None => String::from(key),
+        }
+    }
 }
 
 mod my_date_format {
@@ -1,13 +1,25 @@
 use std::sync::LazyLock;
 
 pub use deadpool_redis::redis;
+use deadpool_redis::redis::streams::{
+    StreamInfoGroupsReply, StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply,
+};
 use deadpool_redis::{self as deadpool, redis::AsyncCommands, Connection};
 
+use crate::models::redis_keys;
+
 static GLOBAL_REDIS_POOL: LazyLock<deadpool::Pool> = LazyLock::new(|| {
     let config = get_config();
     config.create_pool(Some(deadpool::Runtime::Tokio1)).unwrap()
 });
 
+/// Streams are trimmed to roughly this many entries
+static STREAM_MAX_LEN: usize = 10_000;
+static STREAM_PAYLOAD_FIELD: &str = "payload";
+
+static KEY_NAMESPACE: LazyLock<String> =
+    LazyLock::new(|| std::env::var("REDIS_NAMESPACE").unwrap_or_default());
+
 pub fn get_connection() -> deadpool::Pool {
     GLOBAL_REDIS_POOL.clone()
 }
@@ -18,6 +30,317 @@ pub fn get_config() -> deadpool::Config {
     deadpool::Config::from_url(url)
 }
 
+/// Prefixes a key or pub/sub channel with the configured namespace (`REDIS_NAMESPACE`),
+/// so several trackers can share one Redis instance
+pub fn key(name: &str) -> String {
+    namespaced(&KEY_NAMESPACE, name)
+}
+
+/// Namespaced key scoped to a single team, see [`redis_keys::for_team`]
+pub fn team_key(name: &str, team_handle: Option<&str>) -> String {
+    key(&redis_keys::for_team(name, team_handle))
+}
+
+/// Escapes the characters `SCAN MATCH` patterns treat specially, so `value` only matches
+/// itself
+pub fn escape_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn namespaced(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        return String::from(name);
+    }
+
+    format!("{namespace}:{name}")
+}
+
+/// Moves keys written before namespacing was configured (including per-team keys) under
+/// the current namespace. Keys that already exist in the namespace are left untouched.
+///
+/// Un-prefixed keys may belong to another tracker sharing the instance without a namespace,
+/// so this only runs into a namespace the poller hasn't written state to yet and returns
+/// `None` otherwise. Streams don't count, the notifier creates them as soon as it starts.
+pub async fn migrate_unprefixed_keys(
+    redis: &mut Connection,
+) -> Result<Option<usize>, anyhow::Error> {
+    if KEY_NAMESPACE.is_empty() {
+        return Err(anyhow::Error::msg(
+            "keys can only be migrated into a namespace, set redis_namespace",
+        ));
+    }
+
+    let state_keys = redis_keys::ALL.iter().filter(|name| {
+        !redis_keys::QUEUE_STREAMS.contains(name)
+            && **name != redis_keys::NOTIFIER_DEAD_LETTER_STREAM
+    });
+    for name in state_keys {
+        if !team_keys(name, redis).await?.is_empty() {
+            return Ok(None);
+        }
+    }
+
+    let mut migrated = 0;
+    for name in redis_keys::ALL {
+        let mut legacy_keys: Vec<String> = vec![];
+        let name = escape_pattern(name);
+        for pattern in [name.clone(), format!("{name}:*")] {
+            let mut iter = redis.scan_match::<_, String>(pattern).await?;
+            while let Some(legacy_key) = iter.next_item().await {
+                legacy_keys.push(legacy_key);
+            }
+        }
+
+        for legacy_key in legacy_keys {
+            let renamed: bool = redis.rename_nx(&legacy_key, key(&legacy_key)).await?;
+            if renamed {
+                migrated += 1;
+            }
+        }
+    }
+
+    Ok(Some(migrated))
+}
+
+/// Moves the state older versions kept in unscoped keys when tracking a single team to the
+/// keys scoped to `team_handle`. Keys the team already has are left untouched, and nothing
+/// is moved if the unscoped keys hold the state of tracking every program (the program list
+/// is only saved then).
+pub async fn migrate_unscoped_team_keys(
+    team_handle: &str,
+    redis: &mut Connection,
+) -> Result<usize, anyhow::Error> {
+    let tracked_all_programs: bool = redis.exists(key(redis_keys::PROGRAMS)).await?;
+    if tracked_all_programs {
+        return Ok(0);
+    }
+
+    let mut migrated = 0;
+    for name in redis_keys::UNSCOPED_TEAM_KEYS {
+        let legacy_key = key(name);
+        let exists: bool = redis.exists(&legacy_key).await?;
+        if !exists {
+            continue;
+        }
+
+        let renamed: bool = redis
+            .rename_nx(&legacy_key, team_key(name, Some(team_handle)))
+            .await?;
+        if renamed {
+            migrated += 1;
+        }
+    }
+
+    Ok(migrated)
+}
+
+/// Every key holding `name`, unscoped or scoped to a team (see [`team_key`])
+pub async fn team_keys(name: &str, redis: &mut Connection) -> Result<Vec<String>, anyhow::Error> {
+    let mut keys = vec![];
+    let name = escape_pattern(&key(name));
+    for pattern in [name.clone(), format!("{}:*", name)] {
+        let mut iter = redis.scan_match::<_, String>(pattern).await?;
+        while let Some(team_key) = iter.next_item().await {
+            keys.push(team_key);
+        }
+    }
+
+    Ok(keys)
+}
+
+/// The latest `count` entries of a stream, newest first
+pub async fn read_stream_latest(
+    stream: &str,
+    count: usize,
+    redis: &mut Connection,
+) -> Result<Vec<StreamEntry>, anyhow::Error> {
+    let reply: StreamRangeReply = redis.xrevrange_count(stream, "+", "-", count).await?;
+    let entries = reply
+        .ids
+        .into_iter()
+        .map(|entry| StreamEntry {
+            payload: entry.get::<String>(STREAM_PAYLOAD_FIELD),
+            id: entry.id,
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// Appends a queue item to a stream, entries stay in the stream until every consumer group
+/// has acknowledged them
+pub async fn publish_to_stream<V: serde::Serialize>(
+    stream: &str,
+    item: &V,
+    redis: &mut Connection,
+) -> Result<String, anyhow::Error> {
+    let payload = serde_json::to_string(item)?;
+    let id = redis
+        .xadd_maxlen(
+            stream,
+            StreamMaxlen::Approx(STREAM_MAX_LEN),
+            "*",
+            &[(STREAM_PAYLOAD_FIELD, payload)],
+        )
+        .await?;
+
+    Ok(id)
+}
+
+/// Queues [`publish_to_stream`] on `pipe`, to publish a queue item in the same transaction as
+/// the state it was derived from
+pub fn pipe_publish_to_stream<V: serde::Serialize>(
+    pipe: &mut redis::Pipeline,
+    stream: &str,
+    item: &V,
+) -> Result<(), anyhow::Error> {
+    let payload = serde_json::to_string(item)?;
+    pipe.xadd_maxlen(
+        stream,
+        StreamMaxlen::Approx(STREAM_MAX_LEN),
+        "*",
+        &[(STREAM_PAYLOAD_FIELD, payload)],
+    )
+    .ignore();
+
+    Ok(())
+}
+
+/// How far a consumer group is behind on a stream
+#[derive(Debug, Default, PartialEq)]
+pub struct StreamBacklog {
+    /// Entries in the stream, including delivered ones that weren't trimmed yet
+    pub length: usize,
+    /// Entries read by the group but not acknowledged yet
+    pub pending: usize,
+    /// Entries the group hasn't read yet, `None` before Redis 7
+    pub lag: Option<usize>,
+}
+
+pub async fn stream_backlog(
+    stream: &str,
+    group: &str,
+    redis: &mut Connection,
+) -> Result<StreamBacklog, anyhow::Error> {
+    let exists: bool = redis.exists(stream).await?;
+    if !exists {
+        return Ok(StreamBacklog::default());
+    }
+
+    let length: usize = redis.xlen(stream).await?;
+    let groups: StreamInfoGroupsReply = redis.xinfo_groups(stream).await?;
+    let group = groups.groups.into_iter().find(|info| info.name == group);
+
+    Ok(StreamBacklog {
+        length,
+        pending: group.as_ref().map_or(0, |info| info.pending),
+        // nothing was read yet without a group
+        lag: group.map_or(Some(length), |info| info.lag),
+    })
+}
+
+#[derive(Debug)]
+pub struct StreamEntry {
+    pub id: String,
+    /// Missing if the entry was trimmed from the stream before it was acknowledged
+    pub payload: Option<String>,
+}
+
+/// Reads a stream as a member of a consumer group. Entries are delivered at least once:
+/// anything read but never acknowledged is handed out again by [`StreamConsumer::read_pending`].
+pub struct StreamConsumer {
+    pub stream: String,
+    pub group: String,
+    pub consumer: String,
+}
+
+impl StreamConsumer {
+    /// Creates the consumer group (and stream) if they don't exist yet. New groups start at
+    /// the beginning of the stream so entries published before the first start are not lost.
+    pub async fn new(
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        redis: &mut Connection,
+    ) -> Result<Self, anyhow::Error> {
+        let created: Result<(), _> = redis.xgroup_create_mkstream(stream, group, "0").await;
+        if let Err(err) = created {
+            if err.code() != Some("BUSYGROUP") {
+                return Err(err.into());
+            }
+        }
+
+        Ok(StreamConsumer {
+            stream: String::from(stream),
+            group: String::from(group),
+            consumer: String::from(consumer),
+        })
+    }
+
+    /// Entries previously delivered to this consumer that were never acknowledged
+    pub async fn read_pending(
+        &self,
+        count: usize,
+        redis: &mut Connection,
+    ) -> Result<Vec<StreamEntry>, anyhow::Error> {
+        self.read("0", count, None, redis).await
+    }
+
+    /// Waits up to `block_ms` for entries that were never delivered to the group
+    pub async fn read_new(
+        &self,
+        count: usize,
+        block_ms: usize,
+        redis: &mut Connection,
+    ) -> Result<Vec<StreamEntry>, anyhow::Error> {
+        self.read(">", count, Some(block_ms), redis).await
+    }
+
+    pub async fn ack(&self, id: &str, redis: &mut Connection) -> Result<(), anyhow::Error> {
+        redis.xack::<_, _, _, ()>(&self.stream, &self.group, &[id]).await?;
+        Ok(())
+    }
+
+    async fn read(
+        &self,
+        id: &str,
+        count: usize,
+        block_ms: Option<usize>,
+        redis: &mut Connection,
+    ) -> Result<Vec<StreamEntry>, anyhow::Error> {
+        let mut options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(count);
+        if let Some(block_ms) = block_ms {
+            options = options.block(block_ms);
+        }
+
+        let reply: Option<StreamReadReply> =
+            redis.xread_options(&[&self.stream], &[id], &options).await?;
+
+        let mut entries = vec![];
+        for key in reply.unwrap_or_default().keys {
+            for entry in key.ids {
+                let payload = entry.get::<String>(STREAM_PAYLOAD_FIELD);
+                entries.push(StreamEntry {
+                    id: entry.id,
+                    payload,
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
 pub async fn save_vec_to_set<'a, V: serde::Deserialize<'a> + serde::Serialize>(
     name: &str,
     data: Vec<V>,
@@ -36,6 +359,62 @@ pub async fn save_vec_to_set<'a, V: serde::Deserialize<'a> + serde::Serialize>(
     Ok(())
 }
 
+/// Queues replacing the set `name` with `data` on `pipe`, like [`save_vec_to_set`] with
+/// `overwrite`
+pub fn pipe_replace_set<V: serde::Serialize>(
+    pipe: &mut redis::Pipeline,
+    name: &str,
+    data: &[V],
+) -> Result<(), anyhow::Error> {
+    pipe.del(name).ignore();
+    for i in data {
+        pipe.sadd(name, serde_json::to_string(i)?).ignore();
+    }
+
+    Ok(())
+}
+
+/// Replaces the set `name` of a team with `data` and marks it saved, so an empty snapshot
+/// reads back as empty instead of as never saved, see [`load_snapshot`]
+pub async fn save_snapshot<V: serde::Serialize>(
+    name: &str,
+    team_handle: Option<&str>,
+    data: &[V],
+    redis: &mut Connection,
+) -> Result<(), anyhow::Error> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    pipe_replace_set(&mut pipe, &team_key(name, team_handle), data)?;
+    pipe.sadd(
+        key(redis_keys::SNAPSHOTS_SAVED),
+        redis_keys::for_team(name, team_handle),
+    )
+    .ignore();
+    pipe.query_async::<()>(redis).await?;
+
+    Ok(())
+}
+
+/// The members of a snapshot saved with [`save_snapshot`], `None` if it was never saved
+pub async fn load_snapshot(
+    name: &str,
+    team_handle: Option<&str>,
+    redis: &mut Connection,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let saved: bool = redis
+        .sismember(
+            key(redis_keys::SNAPSHOTS_SAVED),
+            redis_keys::for_team(name, team_handle),
+        )
+        .await?;
+    if !saved {
+        return Ok(None);
+    }
+
+    let members = load_set_to_vec(&team_key(name, team_handle), redis).await?;
+    Ok(Some(members))
+}
+
 pub async fn load_set_to_vec(
     name: &str,
     redis: &mut Connection,
@@ -52,3 +431,24 @@ pub async fn load_set_to_vec(
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_namespace_keys() {
+        assert_eq!(namespaced("", "programs"), "programs");
+        assert_eq!(namespaced("tenant", "programs"), "tenant:programs");
+        assert_eq!(
+            namespaced("tenant", &redis_keys::for_team("reports_poll_last_data", Some("security"))),
+            "tenant:reports_poll_last_data:security"
+        );
+    }
+
+    #[test]
+    fn can_escape_patterns() {
+        assert_eq!(escape_pattern("hackermon"), "hackermon");
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+}
@@ -0,0 +1,108 @@
+//! Every reputation change of a researcher in a program, kept as a sorted set scored by the
+//! time of the change so ranges of time can be read back, e.g. the last 90 days. Histories
+//! are keyed by the researcher's database id, which stays the same when they're renamed.
+use chrono::NaiveDateTime;
+use deadpool_redis::redis::{AsyncCommands, Pipeline};
+use deadpool_redis::Connection;
+
+use crate::models::{redis_keys, ReputationPoint};
+use crate::redis;
+
+/// Points older than this are dropped when new points are recorded
+pub static RETENTION_DAYS: i64 = 365;
+
+/// Handles are matched case-insensitively
+fn history_key(team_handle: &str, user_id: &str) -> String {
+    format!(
+        "{}:{}:{}",
+        redis_keys::REPUTATION_HISTORY,
+        team_handle.to_lowercase(),
+        user_id.to_lowercase()
+    )
+}
+
+fn programs_key(user_id: &str) -> String {
+    format!(
+        "{}:{}",
+        redis_keys::REPUTATION_HISTORY_PROGRAMS,
+        user_id.to_lowercase()
+    )
+}
+
+/// Queues appending a point to the history of a researcher in a program on `pipe`, dropping
+/// points past [`RETENTION_DAYS`], so it's saved together with the leaderboard it was taken
+/// from
+pub fn record(
+    team_handle: &str,
+    user_id: &str,
+    point: &ReputationPoint,
+    pipe: &mut Pipeline,
+) -> Result<(), anyhow::Error> {
+    let key = redis::key(&history_key(team_handle, user_id));
+    let programs_key = redis::key(&programs_key(user_id));
+    let recorded_at = point.recorded_at.and_utc().timestamp_millis();
+    let retention_start = recorded_at - chrono::Duration::days(RETENTION_DAYS).num_milliseconds();
+    let retention_seconds = chrono::Duration::days(RETENTION_DAYS).num_seconds();
+
+    pipe.zadd(&key, serde_json::to_string(point)?, recorded_at)
+        .ignore()
+        .zrembyscore(&key, "-inf", format!("({}", retention_start))
+        .ignore()
+        // researchers that drop off a leaderboard stop getting points
+        .expire(&key, retention_seconds)
+        .ignore()
+        .sadd(&programs_key, team_handle.to_lowercase())
+        .ignore()
+        .expire(&programs_key, retention_seconds)
+        .ignore();
+
+    Ok(())
+}
+
+/// Points of a researcher in a program recorded between `since` and `until` (inclusive),
+/// oldest first
+pub async fn query(
+    team_handle: &str,
+    user_id: &str,
+    since: NaiveDateTime,
+    until: NaiveDateTime,
+    kv: &mut Connection,
+) -> Result<Vec<ReputationPoint>, anyhow::Error> {
+    let key = redis::key(&history_key(team_handle, user_id));
+    let members: Vec<String> = kv
+        .zrangebyscore(
+            &key,
+            since.and_utc().timestamp_millis(),
+            until.and_utc().timestamp_millis(),
+        )
+        .await?;
+
+    let mut points = vec![];
+    for member in members {
+        points.push(serde_json::from_str::<ReputationPoint>(&member)?);
+    }
+
+    Ok(points)
+}
+
+/// Programs a researcher has recorded points in, including programs whose points have all
+/// been dropped since
+pub async fn programs(user_id: &str, kv: &mut Connection) -> Result<Vec<String>, anyhow::Error> {
+    let mut programs: Vec<String> = kv.smembers(redis::key(&programs_key(user_id))).await?;
+    programs.sort();
+    Ok(programs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keys_ignore_case() {
+        assert_eq!(
+            history_key("Security", "1337"),
+            "reputation_history:security:1337"
+        );
+        assert_eq!(programs_key("AbC"), "reputation_history_programs:abc");
+    }
+}
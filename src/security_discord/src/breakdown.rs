@@ -1,3 +1,5 @@
+use std::fmt;
+
 static HIGH: i32 = 50;
 static MEDIUM: i32 = 25;
 static LOW: i32 = 15;
@@ -15,8 +17,8 @@ pub struct ReputationBreakdown {
     spam: i32,
 }
 
-impl ToString for ReputationBreakdown {
-    fn to_string(&self) -> String {
+impl fmt::Display for ReputationBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut parts = Vec::new();
 
         macro_rules! add_part {
@@ -38,7 +40,7 @@ impl ToString for ReputationBreakdown {
         add_part!(self.not_applicable, "N/A", self.not_applicable);
         add_part!(self.spam, "Spam", self.spam);
 
-        parts.join(", ")
+        write!(f, "{}", parts.join(", "))
     }
 }
 
@@ -0,0 +1,231 @@
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+use crate::metrics;
+use crate::notifier::{self, EventKind, Rejected};
+
+pub static SIGNATURE_HEADER: &str = "x-tracker-signature";
+pub static TIMESTAMP_HEADER: &str = "x-tracker-timestamp";
+pub static EVENT_HEADER: &str = "x-tracker-event";
+pub static IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+static MAX_TRIES: u32 = 5;
+
+static TARGETS: LazyLock<RwLock<Vec<JsonWebhook>>> = LazyLock::new(|| RwLock::new(vec![]));
+static HTTP_REQUEST_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("failed to build request client")
+});
+
+/// Receives raw queue items as JSON, signed with HMAC-SHA256 over `"{timestamp}.{body}"`
+pub struct JsonWebhook {
+    url: String,
+    secret: String,
+    retry_delay: Duration,
+}
+
+impl JsonWebhook {
+    pub fn new(url: &str, secret: &str) -> Result<Self, anyhow::Error> {
+        let parsed = reqwest::Url::parse(url)?;
+        if !["http", "https"].contains(&parsed.scheme()) {
+            return Err(anyhow::Error::msg(format!(
+                "invalid json webhook url {}",
+                url
+            )));
+        }
+
+        Ok(JsonWebhook {
+            url: String::from(url),
+            secret: String::from(secret),
+            retry_delay: Duration::from_secs(1),
+        })
+    }
+
+    /// Posts the body, retrying network errors, 429 and 5xx responses with exponential backoff.
+    /// Other responses fail with [`Rejected`] since sending the same payload again won't
+    /// change them.
+    async fn post(
+        &self,
+        event: EventKind,
+        idempotency_key: &str,
+        body: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut tries = 0;
+        loop {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let signature = sign(&self.secret, timestamp, body);
+            metrics::record_attempt("json");
+            let result = HTTP_REQUEST_CLIENT
+                .post(&self.url)
+                .header("content-type", "application/json")
+                .header(EVENT_HEADER, event.as_str())
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+                .header(IDEMPOTENCY_KEY_HEADER, idempotency_key)
+                .body(String::from(body))
+                .send()
+                .await;
+
+            tries += 1;
+            match result {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) if !is_retryable(response.status()) => {
+                    metrics::record_failure("json");
+                    return Err(Rejected(format!(
+                        "json webhook {} rejected {}: {}",
+                        self.url,
+                        idempotency_key,
+                        response.status()
+                    ))
+                    .into());
+                }
+                Ok(response) => error!("json webhook {} failed: {}", self.url, response.status()),
+                Err(err) => error!("json webhook {} failed: {}", self.url, err),
+            }
+
+            if tries >= MAX_TRIES {
+                metrics::record_failure("json");
+                return Err(anyhow::Error::msg(format!(
+                    "failed to deliver to json webhook {} ({} tries)",
+                    self.url, MAX_TRIES
+                )));
+            }
+
+            metrics::record_retry("json", "failure");
+            tokio::time::sleep(self.retry_delay * 2u32.pow(tries - 1)).await;
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub async fn add_target(target: JsonWebhook) {
+    let mut targets = TARGETS.write().await;
+    targets.push(target);
+}
+
+/// Posts a raw queue item to every configured JSON webhook. `id` is the queue item's id,
+/// items queued before they had one fall back to a hash of their contents.
+pub async fn deliver<V: Serialize>(
+    event: EventKind,
+    id: Option<&str>,
+    item: &V,
+) -> Result<(), anyhow::Error> {
+    let targets = TARGETS.read().await;
+    if targets.is_empty() {
+        return Ok(());
+    }
+
+    let body = serde_json::to_string(item)?;
+    let idempotency_key = match id {
+        Some(id) => String::from(id),
+        None => hex::encode(Sha256::digest(body.as_bytes())),
+    };
+
+    let mut results = vec![];
+    for target in targets.iter() {
+        results.push(target.post(event, &idempotency_key, &body).await);
+    }
+
+    notifier::combine(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stand_in;
+
+    fn target(url: &str) -> JsonWebhook {
+        let mut target = JsonWebhook::new(url, "secret").unwrap();
+        target.retry_delay = Duration::from_millis(10);
+        target
+    }
+
+    #[tokio::test]
+    async fn signs_payload() {
+        let mut server = stand_in::spawn(vec![stand_in::Response::ok()]).await;
+        let body = r#"{"id":"abc"}"#;
+        target(&server.url)
+            .post(EventKind::Reports, "abc", body)
+            .await
+            .unwrap();
+
+        let request = server.next_request().await;
+        let timestamp = request.headers[TIMESTAMP_HEADER].parse::<u64>().unwrap();
+        let expected_signature = format!("sha256={}", sign("secret", timestamp, body));
+
+        assert_eq!(request.body, body);
+        assert_eq!(request.headers[SIGNATURE_HEADER], expected_signature);
+        assert_eq!(request.headers[EVENT_HEADER], "reports");
+        assert_eq!(request.headers[IDEMPOTENCY_KEY_HEADER], "abc");
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let mut server = stand_in::spawn(vec![
+            stand_in::Response::status(503),
+            stand_in::Response::status(429),
+            stand_in::Response::ok(),
+        ])
+        .await;
+
+        target(&server.url)
+            .post(EventKind::Reputation, "abc", "{}")
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let request = server.next_request().await;
+            assert_eq!(request.headers[IDEMPOTENCY_KEY_HEADER], "abc");
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_tries() {
+        let server = stand_in::spawn(vec![stand_in::Response::status(500)]).await;
+        let result = target(&server.url)
+            .post(EventKind::Reputation, "abc", "{}")
+            .await;
+
+        assert!(!notifier::is_rejected(&result.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_rejected() {
+        let mut server = stand_in::spawn(vec![stand_in::Response::status(403)]).await;
+        let result = target(&server.url)
+            .post(EventKind::Reputation, "abc", "{}")
+            .await;
+
+        assert!(notifier::is_rejected(&result.unwrap_err()));
+        server.next_request().await;
+        let retried = tokio::time::timeout(Duration::from_millis(200), server.next_request());
+        assert!(retried.await.is_err());
+    }
+
+    #[test]
+    fn can_sign() {
+        // echo -n '1700000000.{}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            sign("secret", 1700000000, "{}"),
+            "b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+}
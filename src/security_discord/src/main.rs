@@ -3,12 +3,20 @@ extern crate pretty_env_logger;
 extern crate log;
 
 mod breakdown;
+mod json_webhook;
+mod metrics;
+mod notifier;
+mod slack;
+#[cfg(test)]
+mod stand_in;
 mod subscriptions;
 mod webhook;
 
 use std::env;
+use std::time::Duration;
 
 use clap::Parser;
+use security_api::health;
 use tokio::sync::mpsc;
 
 #[derive(Default, Debug, Parser)]
@@ -17,8 +25,47 @@ struct Arguments {
     #[arg(short = 'R', long = "redis", env = "REDIS_URL")]
     redis: String,
 
+    /// Prefix for every Redis key and channel, must match the poller's namespace
+    #[arg(long = "redis_namespace", env = "REDIS_NAMESPACE", default_value = "")]
+    redis_namespace: String,
+
+    /// Name this notifier reads the queue streams as, keep it stable across restarts so
+    /// unacknowledged notifications are redelivered
+    #[arg(long = "consumer_name", env = "CONSUMER_NAME", default_value = "security_discord")]
+    consumer_name: String,
+
     #[arg(short = 'W', long = "webhook_url", env = "DISCORD_WEBHOOK_URL")]
-    discord_webhook_url: String,
+    discord_webhook_url: Option<String>,
+
+    #[arg(short = 'S', long = "slack_webhook_url", env = "SLACK_WEBHOOK_URL")]
+    slack_webhook_url: Option<String>,
+
+    /// URLs the raw queue items are posted to as JSON (comma separated)
+    #[arg(long = "json_webhook_url", env = "JSON_WEBHOOK_URL", value_delimiter = ',')]
+    json_webhook_urls: Vec<String>,
+
+    /// Secret used to sign JSON webhook payloads with HMAC-SHA256
+    #[arg(long = "json_webhook_secret", env = "JSON_WEBHOOK_SECRET", default_value = "")]
+    json_webhook_secret: String,
+
+    /// Routing rules in the format `<event>:<program>=<webhook url>` (comma separated),
+    /// notifications no rule matches go to webhook_url and slack_webhook_url
+    #[arg(long = "route", env = "ROUTES", value_delimiter = ',')]
+    routes: Vec<String>,
+
+    /// Address Prometheus metrics (`/metrics`) and the health endpoints (`/healthz` and
+    /// `/readyz`) are served on, disabled if empty
+    #[arg(long = "metrics_listen", env = "METRICS_LISTEN", default_value = "0.0.0.0:9090")]
+    metrics_listen: String,
+}
+
+/// Slack webhooks are recognized by their url, every other url has to be a Discord webhook
+async fn build_sink(url: &str) -> Result<Box<dyn notifier::Sink>, anyhow::Error> {
+    if url.starts_with("https://hooks.slack.com/") {
+        Ok(Box::new(slack::SlackSink::new(url)?))
+    } else {
+        Ok(Box::new(webhook::DiscordSink::new(url).await?))
+    }
 }
 
 #[tokio::main]
@@ -28,19 +75,85 @@ async fn main() {
     let args = Arguments::parse();
     debug!("{:#?}", args);
 
-    webhook::set_webhook_url(&args.discord_webhook_url)
-        .await
-        .expect("invalid webhook");
+    let discord_webhook_url = args.discord_webhook_url.filter(|url| !url.is_empty());
+    let slack_webhook_url = args.slack_webhook_url.filter(|url| !url.is_empty());
+    let json_webhook_urls: Vec<&String> =
+        args.json_webhook_urls.iter().filter(|url| !url.is_empty()).collect();
+    let routes: Vec<&String> = args.routes.iter().filter(|rule| !rule.is_empty()).collect();
+    if discord_webhook_url.is_none()
+        && slack_webhook_url.is_none()
+        && json_webhook_urls.is_empty()
+        && routes.is_empty()
+    {
+        panic!("no output configured, set webhook_url, slack_webhook_url, json_webhook_url and/or route");
+    }
+
+    if let Some(discord_webhook_url) = &discord_webhook_url {
+        let sink = webhook::DiscordSink::new(discord_webhook_url)
+            .await
+            .expect("invalid webhook");
+        notifier::add_sink(Box::new(sink)).await;
+    }
+
+    if let Some(slack_webhook_url) = &slack_webhook_url {
+        let sink = slack::SlackSink::new(slack_webhook_url).expect("invalid slack webhook");
+        notifier::add_sink(Box::new(sink)).await;
+    }
+
+    // rules for the same webhook share one sink
+    let mut routed_sinks: Vec<(&str, Vec<notifier::Route>)> = vec![];
+    for rule in routes {
+        let (route, url) = notifier::parse_route(rule).expect("invalid route");
+        match routed_sinks.iter_mut().find(|(sink_url, _)| *sink_url == url) {
+            Some((_, sink_routes)) => sink_routes.push(route),
+            None => routed_sinks.push((url, vec![route])),
+        }
+    }
+
+    for (url, sink_routes) in routed_sinks {
+        let sink = build_sink(url).await.expect("invalid route webhook");
+        notifier::add_routed_sink(sink, sink_routes).await;
+    }
+
+    if !json_webhook_urls.is_empty() && args.json_webhook_secret.is_empty() {
+        panic!("json_webhook_secret is required to sign json webhook payloads");
+    }
+
+    for json_webhook_url in json_webhook_urls {
+        let target = json_webhook::JsonWebhook::new(json_webhook_url, &args.json_webhook_secret)
+            .expect("invalid json webhook");
+        json_webhook::add_target(target).await;
+    }
+
     env::set_var("REDIS_URL", &args.redis);
-    subscriptions::reputation::consume_backlog()
-        .await
-        .expect("failed to consume reputation backlog");
+    env::set_var("REDIS_NAMESPACE", &args.redis_namespace);
+
+    if !args.metrics_listen.is_empty() {
+        let metrics_listen = args.metrics_listen.clone();
+        let readiness = health::router(|| async { vec![health::check_redis().await] });
+        let router = security_api::metrics::router().merge(readiness);
+        tokio::spawn(async move {
+            security_api::metrics::serve(&metrics_listen, router).await;
+        });
+
+        tokio::spawn(async move {
+            let interval = Duration::from_secs(15);
+            loop {
+                if let Err(err) = metrics::update_queue_depths().await {
+                    error!("failed to update queue depths: {}", err);
+                }
+
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
 
     let mut tasks = vec![];
 
     {
+        let consumer_name = args.consumer_name.clone();
         let reputation_task = tokio::task::spawn(async move {
-            subscriptions::reputation::reputation_subscription()
+            subscriptions::reputation::reputation_subscription(&consumer_name)
                 .await
                 .expect("reputation subscription failed");
         });
@@ -49,8 +162,9 @@ async fn main() {
     }
 
     {
+        let consumer_name = args.consumer_name.clone();
         let reports_task = tokio::task::spawn(async move {
-            subscriptions::reports::reports_subscription()
+            subscriptions::reports::reports_subscription(&consumer_name)
                 .await
                 .expect("reports subscription failed");
         });
@@ -59,8 +173,9 @@ async fn main() {
     }
 
     {
+        let consumer_name = args.consumer_name.clone();
         let informative_reports_task = tokio::task::spawn(async move {
-            subscriptions::informative_reports::informative_reports_subscription()
+            subscriptions::informative_reports::informative_reports_subscription(&consumer_name)
                 .await
                 .expect("leaderboard reports subscription failed");
         });
@@ -68,6 +183,61 @@ async fn main() {
         tasks.push(informative_reports_task);
     }
 
+    {
+        let consumer_name = args.consumer_name.clone();
+        let programs_task = tokio::task::spawn(async move {
+            subscriptions::programs::programs_subscription(&consumer_name)
+                .await
+                .expect("programs subscription failed");
+        });
+
+        tasks.push(programs_task);
+    }
+
+    {
+        let consumer_name = args.consumer_name.clone();
+        let structured_scopes_task = tokio::task::spawn(async move {
+            subscriptions::structured_scopes::structured_scopes_subscription(&consumer_name)
+                .await
+                .expect("structured scopes subscription failed");
+        });
+
+        tasks.push(structured_scopes_task);
+    }
+
+    {
+        let consumer_name = args.consumer_name.clone();
+        let bounty_tables_task = tokio::task::spawn(async move {
+            subscriptions::bounty_tables::bounty_tables_subscription(&consumer_name)
+                .await
+                .expect("bounty tables subscription failed");
+        });
+
+        tasks.push(bounty_tables_task);
+    }
+
+    {
+        let consumer_name = args.consumer_name.clone();
+        let policy_task = tokio::task::spawn(async move {
+            subscriptions::policy::policy_subscription(&consumer_name)
+                .await
+                .expect("policy subscription failed");
+        });
+
+        tasks.push(policy_task);
+    }
+
+    {
+        let consumer_name = args.consumer_name.clone();
+        let poll_status_task = tokio::task::spawn(async move {
+            subscriptions::poll_status::poll_status_subscription(&consumer_name)
+                .await
+                .expect("poll status subscription failed");
+        });
+
+        tasks.push(poll_status_task);
+    }
+
     // Wait for any task to abort
     let (abort_sender, mut abort_receiver) = mpsc::channel(1);
     for task in tasks {
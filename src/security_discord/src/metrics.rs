@@ -0,0 +1,96 @@
+use std::sync::LazyLock;
+
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use security_api::models;
+use security_api::redis;
+
+static WEBHOOK_ATTEMPTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tracker_webhook_attempts_total",
+        "Webhook requests sent, including retries",
+        &["sink"]
+    )
+    .unwrap()
+});
+
+static WEBHOOK_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tracker_webhook_retries_total",
+        "Webhook requests sent again by reason (rate_limited, server_error, network or failure)",
+        &["sink", "reason"]
+    )
+    .unwrap()
+});
+
+static WEBHOOK_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tracker_webhook_failures_total",
+        "Messages that were rejected or given up on",
+        &["sink"]
+    )
+    .unwrap()
+});
+
+static QUEUE_LENGTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "tracker_queue_length",
+        "Entries in a queue stream",
+        &["stream"]
+    )
+    .unwrap()
+});
+
+static QUEUE_PENDING: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "tracker_queue_pending",
+        "Queue entries read by the notifier but not acknowledged yet",
+        &["stream"]
+    )
+    .unwrap()
+});
+
+static QUEUE_LAG: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "tracker_queue_lag",
+        "Queue entries the notifier hasn't read yet (Redis 7 and later)",
+        &["stream"]
+    )
+    .unwrap()
+});
+
+pub fn record_attempt(sink: &str) {
+    WEBHOOK_ATTEMPTS.with_label_values(&[sink]).inc();
+}
+
+pub fn record_retry(sink: &str, reason: &str) {
+    WEBHOOK_RETRIES.with_label_values(&[sink, reason]).inc();
+}
+
+pub fn record_failure(sink: &str) {
+    WEBHOOK_FAILURES.with_label_values(&[sink]).inc();
+}
+
+/// Reads the length and backlog of every queue stream into the queue gauges
+pub async fn update_queue_depths() -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    for stream in models::redis_keys::QUEUE_STREAMS {
+        let backlog = redis::stream_backlog(
+            &redis::key(stream),
+            models::redis_keys::NOTIFIER_CONSUMER_GROUP,
+            &mut kv,
+        )
+        .await?;
+
+        QUEUE_LENGTH
+            .with_label_values(&[stream])
+            .set(backlog.length as i64);
+        QUEUE_PENDING
+            .with_label_values(&[stream])
+            .set(backlog.pending as i64);
+        if let Some(lag) = backlog.lag {
+            QUEUE_LAG.with_label_values(&[stream]).set(lag as i64);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,570 @@
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+/// The queue a notification or raw queue item came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Reputation,
+    Reports,
+    InformativeReports,
+    Programs,
+    Scopes,
+    BountyTables,
+    Policy,
+    PollStatus,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Reputation => "reputation",
+            EventKind::Reports => "reports",
+            EventKind::InformativeReports => "informative_reports",
+            EventKind::Programs => "programs",
+            EventKind::Scopes => "scopes",
+            EventKind::BountyTables => "bounty_tables",
+            EventKind::Policy => "policy",
+            EventKind::PollStatus => "poll_status",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(event: &str) -> Result<Self, Self::Err> {
+        match event {
+            "reputation" => Ok(EventKind::Reputation),
+            "reports" => Ok(EventKind::Reports),
+            "informative_reports" => Ok(EventKind::InformativeReports),
+            "programs" => Ok(EventKind::Programs),
+            "scopes" => Ok(EventKind::Scopes),
+            "bounty_tables" => Ok(EventKind::BountyTables),
+            "policy" => Ok(EventKind::Policy),
+            "poll_status" => Ok(EventKind::PollStatus),
+            _ => Err(anyhow::Error::msg(format!("unknown event kind {}", event))),
+        }
+    }
+}
+
+/// A platform-neutral message built from a queue item, every [`Sink`] renders it in its own format
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Notification {
+    pub color: u32,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub description: Option<RichText>,
+    pub fields: Vec<Field>,
+    pub footer: Option<String>,
+    /// A small image shown next to the title, e.g. the program's avatar
+    pub thumbnail_url: Option<String>,
+    /// The program this notification is about, used to route it
+    pub team_handle: Option<String>,
+}
+
+impl Notification {
+    pub fn new(color: u32) -> Self {
+        Notification {
+            color,
+            ..Default::default()
+        }
+    }
+
+    pub fn title(mut self, title: &str, url: &str) -> Self {
+        self.title = Some(String::from(title));
+        self.url = Some(String::from(url));
+        self
+    }
+
+    pub fn description(mut self, description: RichText) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    pub fn field(mut self, name: &str, value: RichText, inline: bool) -> Self {
+        self.fields.push(Field {
+            name: String::from(name),
+            value,
+            inline,
+        });
+        self
+    }
+
+    pub fn footer(mut self, footer: &str) -> Self {
+        self.footer = Some(String::from(footer));
+        self
+    }
+
+    pub fn thumbnail(mut self, url: &str) -> Self {
+        self.thumbnail_url = Some(String::from(url));
+        self
+    }
+
+    pub fn team_handle(mut self, team_handle: Option<&str>) -> Self {
+        self.team_handle = team_handle.map(String::from);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub value: RichText,
+    pub inline: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Span {
+    Text(String),
+    Bold(String),
+    /// A unified diff excerpt, rendered as a code block on its own lines
+    Diff(String),
+    /// A highlighted link, used for HackerOne users and programs
+    Link {
+        text: String,
+        url: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RichText {
+    pub spans: Vec<Span>,
+}
+
+impl RichText {
+    pub fn new() -> Self {
+        RichText::default()
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.spans.push(Span::Text(String::from(text)));
+        self
+    }
+
+    pub fn bold(mut self, text: &str) -> Self {
+        self.spans.push(Span::Bold(String::from(text)));
+        self
+    }
+
+    pub fn diff(mut self, diff: &str) -> Self {
+        self.spans.push(Span::Diff(String::from(diff)));
+        self
+    }
+
+    pub fn link(mut self, text: &str, url: &str) -> Self {
+        self.spans.push(Span::Link {
+            text: String::from(text),
+            url: String::from(url),
+        });
+        self
+    }
+
+    /// Link to a HackerOne user or program profile
+    pub fn profile(self, handle: &str) -> Self {
+        self.link(handle, &format!("https://hackerone.com/{}", handle))
+    }
+
+    pub fn append(mut self, mut other: RichText) -> Self {
+        self.spans.append(&mut other.spans);
+        self
+    }
+
+    /// Joins several texts into "a, b and c"
+    pub fn join(items: Vec<RichText>) -> Self {
+        let count = items.len();
+        let mut joined = RichText::new();
+        for (index, item) in items.into_iter().enumerate() {
+            if index > 0 {
+                joined = joined.text(if index == count - 1 { " and " } else { ", " });
+            }
+
+            joined = joined.append(item);
+        }
+
+        joined
+    }
+
+    /// Renders the text with every span formatted by `render`
+    pub fn render(&self, render: impl Fn(&Span) -> String) -> String {
+        self.spans.iter().map(render).collect()
+    }
+}
+
+impl From<&str> for RichText {
+    fn from(text: &str) -> Self {
+        RichText::new().text(text)
+    }
+}
+
+/// A delivery the receiver refused for good, e.g. to a deleted webhook. Sending it again
+/// won't change the response, so the queue entry is dead-lettered instead of retried.
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+pub fn is_rejected(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Rejected>().is_some()
+}
+
+/// Combines the results of delivering one queue item to several receivers. Fails if any of
+/// them failed, as [`Rejected`] only if every failure was a rejection so that temporary
+/// failures are still retried.
+pub fn combine(results: Vec<Result<(), anyhow::Error>>) -> Result<(), anyhow::Error> {
+    let errors: Vec<anyhow::Error> = results.into_iter().filter_map(Result::err).collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    let rejected = errors.iter().all(is_rejected);
+    let message = errors
+        .iter()
+        .map(|err| err.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    if rejected {
+        Err(Rejected(message).into())
+    } else {
+        Err(anyhow::Error::msg(message))
+    }
+}
+
+/// An output notifications are delivered to, such as a Discord or Slack webhook
+#[async_trait]
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn deliver(&self, notifications: &[Notification]) -> Result<(), anyhow::Error>;
+}
+
+/// Which notifications a routed sink receives, `None` matches every event kind or program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub event: Option<EventKind>,
+    pub team_handle: Option<String>,
+}
+
+impl Route {
+    fn matches(&self, event: EventKind, team_handle: Option<&str>) -> bool {
+        let event_matches = self.event.is_none_or(|route_event| route_event == event);
+        let team_matches = match (&self.team_handle, team_handle) {
+            (None, _) => true,
+            (Some(route_handle), Some(handle)) => route_handle.eq_ignore_ascii_case(handle),
+            (Some(_), None) => false,
+        };
+
+        event_matches && team_matches
+    }
+}
+
+/// Parses a routing rule in the format `<event>:<program>=<webhook url>`, where `*` matches
+/// every event kind or program, e.g. `reputation:security=https://discord.com/api/webhooks/...`
+pub fn parse_route(rule: &str) -> Result<(Route, &str), anyhow::Error> {
+    let invalid = || {
+        anyhow::Error::msg(format!(
+            "invalid route {}, ensure it's format: <event>:<program>=<webhook url>",
+            rule
+        ))
+    };
+    let (matcher, url) = rule.split_once('=').ok_or_else(invalid)?;
+    let (event, team_handle) = matcher.split_once(':').ok_or_else(invalid)?;
+    if team_handle.is_empty() || url.is_empty() {
+        return Err(invalid());
+    }
+
+    let event = match event {
+        "*" => None,
+        event => Some(event.parse::<EventKind>()?),
+    };
+
+    let team_handle = match team_handle {
+        "*" => None,
+        team_handle => Some(String::from(team_handle)),
+    };
+
+    Ok((Route { event, team_handle }, url))
+}
+
+struct Output {
+    sink: Box<dyn Sink>,
+    /// Outputs without routes receive every notification no route matched
+    routes: Vec<Route>,
+}
+
+impl Output {
+    fn is_fallback(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    fn matches(&self, event: EventKind, team_handle: Option<&str>) -> bool {
+        self.routes
+            .iter()
+            .any(|route| route.matches(event, team_handle))
+    }
+}
+
+static OUTPUTS: LazyLock<RwLock<Vec<Output>>> = LazyLock::new(|| RwLock::new(vec![]));
+
+pub async fn add_sink(sink: Box<dyn Sink>) {
+    add_routed_sink(sink, vec![]).await;
+}
+
+pub async fn add_routed_sink(sink: Box<dyn Sink>, routes: Vec<Route>) {
+    let mut outputs = OUTPUTS.write().await;
+    outputs.push(Output { sink, routes });
+}
+
+/// Delivers notifications to every sink with a matching route, notifications no route
+/// matches go to the sinks added without routes.
+///
+/// Every sink is tried even if another one failed, the failures are then returned together
+/// (see [`combine`]). The queue item is retried as a whole, so delivery is at least once: a
+/// sink that already got the notifications gets them again on the retry.
+pub async fn deliver(
+    event: EventKind,
+    notifications: Vec<Notification>,
+) -> Result<(), anyhow::Error> {
+    if notifications.is_empty() {
+        return Ok(());
+    }
+
+    let outputs = OUTPUTS.read().await;
+    deliver_to(&outputs, event, notifications).await
+}
+
+async fn deliver_to(
+    outputs: &[Output],
+    event: EventKind,
+    notifications: Vec<Notification>,
+) -> Result<(), anyhow::Error> {
+    let mut results = vec![];
+    for (output, notifications) in route(outputs, event, notifications) {
+        let count = notifications.len();
+        trace!(
+            "delivering {} notifications to {}",
+            count,
+            output.sink.name()
+        );
+
+        let result = output.sink.deliver(&notifications).await;
+        if let Err(err) = &result {
+            error!(
+                "failed to deliver {} notifications to {}: {:#}",
+                count,
+                output.sink.name(),
+                err
+            );
+        }
+        results.push(result);
+    }
+
+    combine(results)
+}
+
+/// Splits notifications up by the outputs they're delivered to, keeping their order
+fn route(
+    outputs: &[Output],
+    event: EventKind,
+    notifications: Vec<Notification>,
+) -> Vec<(&Output, Vec<Notification>)> {
+    let mut routed: Vec<Vec<Notification>> = outputs.iter().map(|_| vec![]).collect();
+    for notification in notifications {
+        let team_handle = notification.team_handle.as_deref();
+        let mut targets: Vec<usize> = (0..outputs.len())
+            .filter(|index| outputs[*index].matches(event, team_handle))
+            .collect();
+
+        if targets.is_empty() {
+            targets = (0..outputs.len())
+                .filter(|index| outputs[*index].is_fallback())
+                .collect();
+        }
+
+        for index in targets {
+            routed[index].push(notification.clone());
+        }
+    }
+
+    outputs
+        .iter()
+        .zip(routed)
+        .filter(|(_, notifications)| !notifications.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_join_rich_text() {
+        let users = vec!["a", "b", "c"]
+            .into_iter()
+            .map(|user| RichText::new().bold(user))
+            .collect();
+
+        let rendered = RichText::join(users).render(|span| match span {
+            Span::Text(text) | Span::Diff(text) => text.clone(),
+            Span::Bold(text) => format!("*{}*", text),
+            Span::Link { text, .. } => text.clone(),
+        });
+
+        assert_eq!(rendered, "*a*, *b* and *c*");
+    }
+
+    struct NamedSink(&'static str);
+
+    #[async_trait]
+    impl Sink for NamedSink {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn deliver(&self, _: &[Notification]) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    fn output(name: &'static str, rules: &[&str]) -> Output {
+        let routes = rules
+            .iter()
+            .map(|rule| parse_route(rule).unwrap().0)
+            .collect();
+
+        Output {
+            sink: Box::new(NamedSink(name)),
+            routes,
+        }
+    }
+
+    #[test]
+    fn routes_by_event_and_program() {
+        let outputs = vec![
+            output("default", &[]),
+            output("security", &["reputation:security=https://example.com"]),
+            output("reports", &["reports:*=https://example.com"]),
+        ];
+
+        let notifications = vec![
+            Notification::new(1).team_handle(Some("Security")),
+            Notification::new(2).team_handle(Some("gitlab")),
+            Notification::new(3).team_handle(Some("security")),
+        ];
+
+        let routed = route(&outputs, EventKind::Reputation, notifications);
+        let colors: Vec<(&str, Vec<u32>)> = routed
+            .iter()
+            .map(|(output, notifications)| {
+                let colors = notifications.iter().map(|n| n.color).collect();
+                (output.sink.name(), colors)
+            })
+            .collect();
+        assert_eq!(colors, vec![("default", vec![2]), ("security", vec![1, 3])]);
+
+        let routed = route(&outputs, EventKind::Reports, vec![Notification::new(4)]);
+        assert_eq!(routed.len(), 1);
+        assert_eq!(routed[0].0.sink.name(), "reports");
+    }
+
+    struct RecordingSink {
+        name: &'static str,
+        error: Option<&'static str>,
+        delivered: std::sync::Arc<std::sync::Mutex<Vec<(&'static str, u32)>>>,
+    }
+
+    #[async_trait]
+    impl Sink for RecordingSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn deliver(&self, notifications: &[Notification]) -> Result<(), anyhow::Error> {
+            let mut delivered = self.delivered.lock().unwrap();
+            delivered.extend(notifications.iter().map(|n| (self.name, n.color)));
+            match self.error {
+                Some(error) => Err(anyhow::Error::msg(error)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_to_every_sink() {
+        let delivered = std::sync::Arc::default();
+        let sink = |name, error| Output {
+            sink: Box::new(RecordingSink {
+                name,
+                error,
+                delivered: std::sync::Arc::clone(&delivered),
+            }),
+            routes: vec![],
+        };
+
+        let outputs = vec![
+            sink("discord", None),
+            sink("slack", Some("timed out")),
+            sink("second discord", None),
+        ];
+        let notifications = vec![Notification::new(1), Notification::new(2)];
+
+        let err = deliver_to(&outputs, EventKind::Reports, notifications)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "timed out");
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            vec![
+                ("discord", 1),
+                ("discord", 2),
+                ("slack", 1),
+                ("slack", 2),
+                ("second discord", 1),
+                ("second discord", 2),
+            ]
+        );
+
+        delivered.lock().unwrap().clear();
+        let outputs = vec![sink("discord", None), sink("slack", None)];
+        assert!(
+            deliver_to(&outputs, EventKind::Reports, vec![Notification::new(3)])
+                .await
+                .is_ok()
+        );
+        assert_eq!(delivered.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn combines_delivery_results() {
+        let rejected = || Err(Rejected(String::from("404")).into());
+        let failed = || Err(anyhow::Error::msg("timed out"));
+
+        assert!(combine(vec![Ok(()), Ok(())]).is_ok());
+        assert!(is_rejected(&combine(vec![Ok(()), rejected()]).unwrap_err()));
+
+        let err = combine(vec![rejected(), failed()]).unwrap_err();
+        assert!(!is_rejected(&err));
+        assert_eq!(err.to_string(), "404, timed out");
+    }
+
+    #[test]
+    fn can_parse_route() {
+        let (route, url) =
+            parse_route("informative_reports:*=https://discord.com/api/webhooks/1/a").unwrap();
+        assert_eq!(route.event, Some(EventKind::InformativeReports));
+        assert_eq!(route.team_handle, None);
+        assert_eq!(url, "https://discord.com/api/webhooks/1/a");
+
+        assert!(parse_route("reputation=https://example.com").is_err());
+        assert!(parse_route("leaderboard:*=https://example.com").is_err());
+    }
+}
@@ -0,0 +1,444 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+
+use crate::metrics;
+use crate::notifier::{Notification, Rejected, RichText, Sink, Span};
+
+#[derive(Serialize, Debug)]
+struct SlackMessage {
+    attachments: Vec<SlackAttachment>,
+}
+
+/// A colored Block Kit attachment, the Slack counterpart of a Discord embed
+#[derive(Serialize, Debug, Clone)]
+pub struct SlackAttachment {
+    color: String,
+    fallback: String,
+    blocks: Vec<Block>,
+}
+
+impl SlackAttachment {
+    pub fn new(color: u32, fallback: &str, blocks: Vec<Block>) -> Self {
+        SlackAttachment {
+            color: format!("#{:06x}", color),
+            fallback: String::from(fallback),
+            blocks,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    Section {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<Text>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<Text>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        accessory: Option<Accessory>,
+    },
+    Context {
+        elements: Vec<Text>,
+    },
+}
+
+impl Block {
+    pub fn section(text: &str) -> Self {
+        Block::Section {
+            text: Some(Text::mrkdwn(&truncate(text, MAX_SECTION_TEXT_CHARACTERS))),
+            fields: vec![],
+            accessory: None,
+        }
+    }
+
+    /// A section with a small image on its right, like a Discord embed thumbnail
+    pub fn section_with_image(text: &str, image_url: &str) -> Self {
+        Block::Section {
+            text: Some(Text::mrkdwn(&truncate(text, MAX_SECTION_TEXT_CHARACTERS))),
+            fields: vec![],
+            accessory: Some(Accessory::Image {
+                image_url: String::from(image_url),
+                alt_text: String::from("thumbnail"),
+            }),
+        }
+    }
+
+    pub fn fields(fields: Vec<Text>) -> Self {
+        Block::Section {
+            text: None,
+            fields,
+            accessory: None,
+        }
+    }
+
+    pub fn context(text: &str) -> Self {
+        Block::Context {
+            elements: vec![Text::mrkdwn(&truncate(text, MAX_SECTION_TEXT_CHARACTERS))],
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Accessory {
+    Image { image_url: String, alt_text: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Text {
+    Mrkdwn { text: String },
+}
+
+impl Text {
+    pub fn mrkdwn(text: &str) -> Self {
+        Text::Mrkdwn {
+            text: String::from(text),
+        }
+    }
+
+    /// A bold label followed by its value, rendered like a Discord embed field
+    pub fn field(name: &str, value: &str) -> Self {
+        let field = format!("*{}*\n{}", name, value);
+        Text::mrkdwn(&truncate(&field, MAX_FIELD_TEXT_CHARACTERS))
+    }
+}
+
+/// Cuts text down to `max` characters, Slack rejects blocks with longer text
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return String::from(text);
+    }
+
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Escapes the characters Slack treats as control sequences in mrkdwn text
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn render_mrkdwn(text: &RichText) -> String {
+    text.render(|span| match span {
+        Span::Text(text) => escape(text),
+        Span::Bold(text) => format!("*{}*", escape(text)),
+        Span::Diff(diff) => format!("\n```{}```", escape(diff)),
+        Span::Link { text, url } => format!("*<{}|{}>*", url, escape(text)),
+    })
+}
+
+/// Slack truncates messages with more attachments than this
+static MAX_ATTACHMENTS_PER_MESSAGE: usize = 20;
+/// Slack rejects sections with longer text or more fields than these
+static MAX_SECTION_TEXT_CHARACTERS: usize = 3000;
+static MAX_FIELD_TEXT_CHARACTERS: usize = 2000;
+static MAX_SECTION_FIELDS: usize = 10;
+
+static MAX_TRIES: u32 = 5;
+
+static HTTP_REQUEST_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .build()
+        .expect("failed to build request client")
+});
+
+pub struct SlackSink {
+    webhook_url: String,
+    retry_delay: Duration,
+}
+
+impl SlackSink {
+    pub fn new(webhook_url: &str) -> Result<Self, anyhow::Error> {
+        if !webhook_url.starts_with("https://hooks.slack.com/services/") {
+            return Err(anyhow::Error::msg("failed to parse slack webhook, ensure webhook url is format: https://hooks.slack.com/services/..."));
+        }
+
+        Ok(SlackSink {
+            webhook_url: String::from(webhook_url),
+            retry_delay: Duration::from_secs(1),
+        })
+    }
+
+    /// Posts one message, waiting out rate limits and retrying network errors and 5xx
+    /// responses with exponential backoff. Other responses fail with [`Rejected`] since they
+    /// won't change when retried.
+    async fn post_message(&self, message: &SlackMessage) -> Result<(), anyhow::Error> {
+        trace!("sending slack message: {:#?}", message);
+        let mut tries = 0;
+
+        loop {
+            metrics::record_attempt("slack");
+            let response = match HTTP_REQUEST_CLIENT
+                .post(&self.webhook_url)
+                .json(message)
+                .send()
+                .await
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    tries += 1;
+                    error!("slack webhook failed {}", err);
+                    self.retry_after_failure(tries, "network").await?;
+                    continue;
+                }
+            };
+
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+
+            let retry_after = response
+                .headers()
+                .get("retry-after")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            let body = response.text().await.unwrap_or_default();
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                // rate limited requests are waited out and don't count as failed tries
+                let delay = retry_after
+                    .map(Duration::from_secs)
+                    .unwrap_or(self.retry_delay);
+                warn!("slack webhook rate limited, retrying after {:?}", delay);
+                metrics::record_retry("slack", "rate_limited");
+                tokio::time::sleep(delay).await;
+            } else if status.is_server_error() {
+                tries += 1;
+                error!("slack webhook failed {}: {}", status, body);
+                self.retry_after_failure(tries, "server_error").await?;
+            } else {
+                // sending the same message again won't change the response
+                metrics::record_failure("slack");
+                return Err(Rejected(format!(
+                    "slack webhook rejected message {}: {}",
+                    status, body
+                ))
+                .into());
+            }
+        }
+    }
+
+    async fn retry_after_failure(&self, tries: u32, reason: &str) -> Result<(), anyhow::Error> {
+        if tries >= MAX_TRIES {
+            metrics::record_failure("slack");
+            return Err(anyhow::Error::msg(format!(
+                "failed to deliver slack message ({} tries)",
+                MAX_TRIES
+            )));
+        }
+
+        metrics::record_retry("slack", reason);
+        tokio::time::sleep(self.retry_delay * 2u32.pow(tries - 1)).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for SlackSink {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    async fn deliver(&self, notifications: &[Notification]) -> Result<(), anyhow::Error> {
+        for batch in notifications.chunks(MAX_ATTACHMENTS_PER_MESSAGE) {
+            let attachments = batch.iter().map(build_attachment).collect();
+            self.post_message(&SlackMessage { attachments }).await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn build_attachment(notification: &Notification) -> SlackAttachment {
+    let mut blocks = vec![];
+    let mut fallback = String::new();
+
+    if let Some(title) = &notification.title {
+        fallback = title.clone();
+        let title = match &notification.url {
+            Some(url) => RichText::new().link(title, url),
+            None => RichText::new().bold(title),
+        };
+
+        let title = render_mrkdwn(&title);
+        match &notification.thumbnail_url {
+            Some(image_url) => blocks.push(Block::section_with_image(&title, image_url)),
+            None => blocks.push(Block::section(&title)),
+        }
+    }
+
+    if let Some(description) = &notification.description {
+        let description = render_mrkdwn(description);
+        if fallback.is_empty() {
+            fallback = description.clone();
+        }
+
+        blocks.push(Block::section(&description));
+    }
+
+    // consecutive inline fields share one section, like inline embed fields share a row
+    let mut inline_fields = vec![];
+    for field in &notification.fields {
+        let text = Text::field(&escape(&field.name), &render_mrkdwn(&field.value));
+        if field.inline {
+            inline_fields.push(text);
+            continue;
+        }
+
+        push_fields(&mut blocks, std::mem::take(&mut inline_fields));
+        blocks.push(Block::fields(vec![text]));
+    }
+
+    push_fields(&mut blocks, inline_fields);
+
+    if let Some(footer) = &notification.footer {
+        blocks.push(Block::context(&escape(footer)));
+    }
+
+    SlackAttachment::new(notification.color, &fallback, blocks)
+}
+
+/// Adds the fields in as many sections as it takes to stay within Slack's field limit
+fn push_fields(blocks: &mut Vec<Block>, fields: Vec<Text>) {
+    for chunk in fields.chunks(MAX_SECTION_FIELDS) {
+        blocks.push(Block::fields(chunk.to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier;
+    use crate::stand_in;
+
+    fn sink(url: &str) -> SlackSink {
+        SlackSink {
+            webhook_url: String::from(url),
+            retry_delay: Duration::from_millis(10),
+        }
+    }
+
+    fn message() -> SlackMessage {
+        let notification = Notification::new(0x4fb05c)
+            .description(RichText::new().profile("user").text(" said <hi>"))
+            .field("Severity", "high".into(), true)
+            .field("Bounty Award", "100 USD".into(), true)
+            .footer("footer");
+
+        SlackMessage {
+            attachments: vec![build_attachment(&notification)],
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_block_kit_message() {
+        let mut server = stand_in::spawn(vec![stand_in::Response::ok()]).await;
+        sink(&server.url).post_message(&message()).await.unwrap();
+
+        let request = server.next_request().await;
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        let attachment = &body["attachments"][0];
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(attachment["color"], "#4fb05c");
+        assert_eq!(attachment["blocks"][0]["type"], "section");
+        assert_eq!(attachment["blocks"][0]["text"]["type"], "mrkdwn");
+        assert_eq!(
+            attachment["blocks"][0]["text"]["text"],
+            "*<https://hackerone.com/user|user>* said &lt;hi&gt;"
+        );
+        assert_eq!(
+            attachment["blocks"][1]["fields"][0]["text"],
+            "*Severity*\nhigh"
+        );
+        assert_eq!(
+            attachment["blocks"][1]["fields"][1]["text"],
+            "*Bounty Award*\n100 USD"
+        );
+        assert_eq!(attachment["blocks"][2]["elements"][0]["text"], "footer");
+    }
+
+    #[tokio::test]
+    async fn retries_failed_delivery() {
+        let mut server = stand_in::spawn(vec![
+            stand_in::Response::status(500),
+            stand_in::Response::ok(),
+        ])
+        .await;
+        sink(&server.url).post_message(&message()).await.unwrap();
+
+        server.next_request().await;
+        server.next_request().await;
+    }
+
+    #[tokio::test]
+    async fn waits_out_rate_limits() {
+        let mut server = stand_in::spawn(vec![
+            stand_in::Response::status(429).header("retry-after", "0"),
+            stand_in::Response::status(429),
+            stand_in::Response::ok(),
+        ])
+        .await;
+        sink(&server.url).post_message(&message()).await.unwrap();
+
+        for _ in 0..3 {
+            server.next_request().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_rejected() {
+        for status in [400, 404] {
+            let mut server = stand_in::spawn(vec![stand_in::Response {
+                status,
+                headers: vec![],
+                body: String::from("invalid_blocks"),
+            }])
+            .await;
+
+            let err = sink(&server.url)
+                .post_message(&message())
+                .await
+                .unwrap_err();
+            assert!(notifier::is_rejected(&err));
+            assert!(err.to_string().contains("invalid_blocks"));
+
+            // not retried
+            server.next_request().await;
+            let retried = tokio::time::timeout(Duration::from_millis(200), server.next_request());
+            assert!(retried.await.is_err());
+        }
+    }
+
+    #[test]
+    fn keeps_sections_within_limits() {
+        let mut notification =
+            Notification::new(0).description(RichText::new().text(&"a".repeat(4000)));
+        for field in 0..12 {
+            notification = notification.field(&field.to_string(), "value".into(), true);
+        }
+
+        let attachment = serde_json::to_value(build_attachment(&notification)).unwrap();
+        let blocks = attachment["blocks"].as_array().unwrap();
+        let description = blocks[0]["text"]["text"].as_str().unwrap();
+
+        assert_eq!(description.chars().count(), MAX_SECTION_TEXT_CHARACTERS);
+        assert!(description.ends_with('…'));
+        assert_eq!(blocks[1]["fields"].as_array().unwrap().len(), 10);
+        assert_eq!(blocks[2]["fields"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn escapes_mrkdwn() {
+        assert_eq!(escape("<a> & <b>"), "&lt;a&gt; &amp; &lt;b&gt;");
+    }
+}
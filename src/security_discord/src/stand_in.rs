@@ -0,0 +1,137 @@
+//! A minimal HTTP/1.1 server standing in for webhook endpoints in tests
+
+use std::collections::HashMap;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Response::status(200)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: String::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+}
+
+pub struct StandIn {
+    pub url: String,
+    requests: mpsc::UnboundedReceiver<Request>,
+}
+
+impl StandIn {
+    pub async fn next_request(&mut self) -> Request {
+        self.requests.recv().await.expect("stand-in server stopped")
+    }
+}
+
+/// Serves `responses` in order, the last one is repeated once the others are used up
+pub async fn spawn(responses: Vec<Response>) -> StandIn {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let (sender, requests) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut served = 0;
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let response = responses
+                .get(served)
+                .or(responses.last())
+                .cloned()
+                .unwrap_or_else(Response::ok);
+            served += 1;
+
+            let request = read_request(&mut stream).await;
+            let _ = sender.send(request);
+            write_response(&mut stream, &response).await;
+        }
+    });
+
+    StandIn { url, requests }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Request {
+    let mut buffer = vec![];
+    let header_end = loop {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.unwrap();
+        buffer.extend_from_slice(&chunk[..read]);
+
+        if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap().split(' ');
+    let method = String::from(request_line.next().unwrap());
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), String::from(value.trim()));
+        }
+    }
+
+    let content_length = headers
+        .get("content-length")
+        .map(|length| length.parse::<usize>().unwrap())
+        .unwrap_or(0);
+
+    let mut body = buffer[header_end..].to_vec();
+    while body.len() < content_length {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.unwrap();
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Request {
+        method,
+        headers,
+        body: String::from_utf8(body).unwrap(),
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, response: &Response) {
+    let mut head = format!("HTTP/1.1 {} Stand-In\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    head.push_str(&format!(
+        "content-length: {}\r\nconnection: close\r\n\r\n",
+        response.body.len()
+    ));
+
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(response.body.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
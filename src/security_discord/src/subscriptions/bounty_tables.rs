@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+
+use security_api::models;
+
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
+
+pub async fn bounty_tables_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
+    info!("starting subscription");
+    consume_stream(
+        models::redis_keys::BOUNTY_TABLES_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
+
+async fn deliver_queue_item(item: models::BountyTableQueueItem) -> Result<(), anyhow::Error> {
+    debug!("bounty tables: recieved message {:#?}", item);
+    info!(
+        "bounty tables: new queue items (id = {}, items = {})",
+        item.id.clone().unwrap(),
+        item.diff.len()
+    );
+
+    let delivered = json_webhook::deliver(EventKind::BountyTables, item.id.as_deref(), &item).await;
+
+    let notifications = item
+        .diff
+        .iter()
+        .map(|diff| build_notification(diff, &item.team_handle, &item.currency))
+        .map(|notification| notification.team_handle(Some(&item.team_handle)))
+        .collect();
+
+    let notified = notifier::deliver(EventKind::BountyTables, notifications).await;
+    notifier::combine(vec![delivered, notified])
+}
+
+/// `(severity, minimum, maximum)` of every severity in a row
+fn amounts(row: &models::BountyTableRowData) -> [(&str, Option<i64>, Option<i64>); 4] {
+    [
+        ("Low", row.low_minimum, row.low),
+        ("Medium", row.medium_minimum, row.medium),
+        ("High", row.high_minimum, row.high),
+        ("Critical", row.critical_minimum, row.critical),
+    ]
+}
+
+fn format_amount(minimum: Option<i64>, maximum: Option<i64>, currency: &str) -> String {
+    match (minimum, maximum) {
+        (_, None) => String::from("none"),
+        (Some(minimum), Some(maximum)) if minimum != maximum => {
+            format!("{}-{} {}", minimum, maximum, currency)
+        }
+        (_, Some(maximum)) => format!("{} {}", maximum, currency),
+    }
+}
+
+fn build_notification(
+    diff: &[models::BountyTableRowData],
+    team_handle: &str,
+    currency: &str,
+) -> Notification {
+    if diff.len() < 2 {
+        panic!("invalid diff data");
+    }
+
+    let old = &diff[0];
+    let new = &diff[1];
+    let asset = new
+        .asset_identifier
+        .as_deref()
+        .or(old.asset_identifier.as_deref())
+        .unwrap_or("every asset");
+
+    let mut increased = false;
+    let mut decreased = false;
+    for ((_, _, old_amount), (_, _, new_amount)) in amounts(old).into_iter().zip(amounts(new)) {
+        match new_amount.unwrap_or(0).cmp(&old_amount.unwrap_or(0)) {
+            Ordering::Greater => increased = true,
+            Ordering::Less => decreased = true,
+            Ordering::Equal => {}
+        }
+    }
+
+    let (action, color) = if old.id.is_empty() {
+        ("added bounties for", models::embed_colors::POSTIVE)
+    } else if new.id.is_empty() {
+        ("removed bounties for", models::embed_colors::NEGATIVE)
+    } else if increased && !decreased {
+        ("increased bounties for", models::embed_colors::MAJOR)
+    } else if decreased && !increased {
+        ("decreased bounties for", models::embed_colors::NEGATIVE)
+    } else {
+        ("changed bounties for", models::embed_colors::INFORMAL)
+    };
+
+    let text = RichText::new()
+        .profile(team_handle)
+        .text(&format!(" {} ", action))
+        .bold(asset);
+
+    let mut notification = Notification::new(color).description(text);
+    for ((severity, old_minimum, old_amount), (_, new_minimum, new_amount)) in
+        amounts(old).into_iter().zip(amounts(new))
+    {
+        let before = format_amount(old_minimum, old_amount, currency);
+        let after = format_amount(new_minimum, new_amount, currency);
+        let value = if old.id.is_empty() || before == after {
+            after
+        } else {
+            format!("{} → {}", before, after)
+        };
+
+        notification = notification.field(severity, value.as_str().into(), true);
+    }
+
+    notification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_before_and_after() {
+        let old = models::BountyTableRowData {
+            id: String::from("1"),
+            high: Some(1000),
+            critical: Some(2500),
+            ..Default::default()
+        };
+        let new = models::BountyTableRowData {
+            critical: Some(5000),
+            critical_minimum: Some(3000),
+            ..old.clone()
+        };
+
+        let notification = build_notification(&[old, new], "security", "USD");
+        assert_eq!(notification.color, models::embed_colors::MAJOR);
+        assert_eq!(notification.fields.len(), 4);
+        assert_eq!(notification.fields[0].value, "none".into());
+        assert_eq!(notification.fields[2].value, "1000 USD".into());
+        assert_eq!(
+            notification.fields[3].value,
+            "2500 USD → 3000-5000 USD".into()
+        );
+    }
+}
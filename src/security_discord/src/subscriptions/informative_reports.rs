@@ -1,83 +1,58 @@
-use futures_util::StreamExt;
 use security_api::models;
-use security_api::redis;
-use twilight_model::channel::message::embed::Embed;
-use twilight_util::builder::embed::EmbedBuilder;
 
-use crate::webhook;
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
 
-pub async fn informative_reports_subscription() -> Result<(), anyhow::Error> {
+pub async fn informative_reports_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
     info!("starting subscription");
+    consume_stream(
+        models::redis_keys::USER_INFORMATIVE_REPORTS_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
 
-    let kv_config = redis::get_config();
-    let kv_config = kv_config.url.unwrap();
-    let redis = redis::redis::Client::open(kv_config)?;
-    let mut pubsub = redis.get_async_pubsub().await?;
-    pubsub
-        .subscribe(models::redis_keys::USER_INFORMATIVE_REPORTS_QUEUE_PUBSUB)
-        .await?;
-
-    let mut stream = pubsub.into_on_message();
-    while let Some(message) = stream.next().await {
-        let payload: String = message.get_payload().unwrap();
-        let item: models::InvalidReportsQueueItem = serde_json::from_str(&payload).unwrap();
-        debug!("recieved item {:#?}", item);
-        info!("new queue items (changes = {})", item.changes.len());
+async fn deliver_queue_item(item: models::InvalidReportsQueueItem) -> Result<(), anyhow::Error> {
+    debug!("recieved item {:#?}", item);
+    info!("new queue items (changes = {})", item.changes.len());
 
-        let embed = build_embed_data(item.changes, &item.team_handle);
-        webhook::deliver_embeds(vec![embed]).await?;
-    }
+    let delivered = json_webhook::deliver(EventKind::InformativeReports, item.id.as_deref(), &item).await;
 
-    Ok(())
+    let notification = build_notification(&item.changes, &item.team_handle)
+        .team_handle(Some(&item.team_handle));
+    let notified = notifier::deliver(EventKind::InformativeReports, vec![notification]).await;
+    notifier::combine(vec![delivered, notified])
 }
 
-fn build_embed_data(
-    changes: Vec<models::UserInvalidReportChange>,
+fn build_notification(
+    changes: &[models::UserInvalidReportChange],
     team_handle: &str,
-) -> Embed {
-    let program_field = format!("[**``{team_handle}``**](https://hackerone.com/{team_handle})");
-    let count_describing_term = if changes.len() == 1 {
-        let user_change = &changes[0];
-        if user_change.invalid_reports == 1 {
-            "a report"
-        } else {
-            &format!("{} reports", user_change.invalid_reports)
-        }
-    } else {
-        "several reports"
+) -> Notification {
+    let count_describing_term = match changes {
+        [change] if change.invalid_reports == 1 => String::from("a report"),
+        [change] => format!("{} reports", change.invalid_reports),
+        _ => String::from("several reports"),
     };
 
-    let users = changes
+    let mut users = changes
         .iter()
-        .map(|c| {
-            let handle = &c.user_name;
-            format!("[**``{handle}``**](https://hackerone.com/{handle})")
-        })
-        .collect::<Vec<String>>();
-
-    let users = if users.len() == 1 {
-        &users[0]
-    } else if users.len() <= 5 {
-        let oxford_cutoff = users.len() - 1;
-        let last = &users[oxford_cutoff];
-        let oxford_cutoff = &users[..oxford_cutoff].join(", ");
-        &format!("{oxford_cutoff} and {last}")
-    } else {
-        let five_users = &users[..5].join(", ");
-        let remaining_count = users[5..].len();
-        let plural = remaining_count > 1;
+        .map(|c| RichText::new().profile(&c.user_name))
+        .collect::<Vec<RichText>>();
+
+    if users.len() > 5 {
+        let remaining_count = users.len() - 5;
+        let plural = if remaining_count > 1 { "users" } else { "user" };
+        users.truncate(5);
+        users.push(RichText::new().text(&format!("{} other {}", remaining_count, plural)));
+    }
 
-        if plural {
-            &format!("{five_users} and {remaining_count} other users")
-        } else {
-            &format!("{five_users} and {remaining_count} other user")
-        }
-    };
+    let text = RichText::new()
+        .profile(team_handle)
+        .text(&format!(" closed {} from ", count_describing_term))
+        .append(RichText::join(users))
+        .text(" as Informative");
 
-    let text =
-        format!("{program_field} closed {count_describing_term} from {users} as Informative");
-        
-    EmbedBuilder::new()
-        .description(text)
-        .color(models::embed_colors::INFORMAL).build()
+    Notification::new(models::embed_colors::INFORMAL).description(text)
 }
@@ -1,3 +1,101 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+
+use security_api::health;
+use security_api::models;
+use security_api::redis::{self, StreamConsumer, StreamEntry};
+use serde::de::DeserializeOwned;
+
+use crate::notifier;
+
+pub mod bounty_tables;
+pub mod policy;
+pub mod poll_status;
+pub mod programs;
 pub mod reports;
 pub mod reputation;
-pub mod informative_reports;
\ No newline at end of file
+pub mod structured_scopes;
+pub mod informative_reports;
+
+static READ_COUNT: usize = 10;
+static READ_BLOCK_MS: usize = 5000;
+/// Reads return at least every `READ_BLOCK_MS`, deliveries waiting out long rate limits are
+/// the only reason to go without a heartbeat for longer
+static STALE_AFTER: Duration = Duration::from_secs(60 * 10);
+
+/// Hands every item queued on `stream` to `deliver` and acknowledges it once `deliver`
+/// succeeds. Entries left unacknowledged by a previous run are redelivered first.
+pub async fn consume_stream<T, F, Fut>(
+    stream: &str,
+    consumer_name: &str,
+    mut deliver: F,
+) -> Result<(), anyhow::Error>
+where
+    T: DeserializeOwned + Debug,
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<(), anyhow::Error>>,
+{
+    let stream = redis::key(stream);
+    let mut kv = redis::get_connection().get().await?;
+    let consumer = StreamConsumer::new(
+        &stream,
+        models::redis_keys::NOTIFIER_CONSUMER_GROUP,
+        consumer_name,
+        &mut kv,
+    )
+    .await?;
+
+    health::register(&stream, STALE_AFTER).await;
+    let mut reading_pending = true;
+    loop {
+        health::beat(&stream).await;
+        let entries = if reading_pending {
+            consumer.read_pending(READ_COUNT, &mut kv).await?
+        } else {
+            consumer.read_new(READ_COUNT, READ_BLOCK_MS, &mut kv).await?
+        };
+
+        if reading_pending {
+            if entries.is_empty() {
+                reading_pending = false;
+                continue;
+            }
+
+            info!("{}: redelivering {} pending entries", stream, entries.len());
+        }
+
+        for entry in entries {
+            match entry.payload.as_deref().map(serde_json::from_str::<T>) {
+                Some(Ok(item)) => match deliver(item).await {
+                    Ok(()) => {}
+                    Err(err) if notifier::is_rejected(&err) => {
+                        error!("{}: dead-lettering entry {}: {}", stream, entry.id, err);
+                        dead_letter(&stream, &entry, &err).await?;
+                    }
+                    Err(err) => return Err(err),
+                },
+                Some(Err(err)) => error!("{}: dropping invalid entry {}: {}", stream, entry.id, err),
+                None => warn!("{}: entry {} was trimmed before delivery", stream, entry.id),
+            }
+
+            consumer.ack(&entry.id, &mut kv).await?;
+        }
+    }
+}
+
+/// Keeps an entry a receiver refused for good in [`models::redis_keys::NOTIFIER_DEAD_LETTER_STREAM`]
+/// so it can be inspected and requeued, instead of blocking the stream
+async fn dead_letter(
+    stream: &str,
+    entry: &StreamEntry,
+    err: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    let payload = entry.payload.as_deref().unwrap_or_default();
+    let dead_letter = models::DeadLetter::new(stream, &entry.id, &err.to_string(), payload);
+
+    let mut kv = redis::get_connection().get().await?;
+    let dead_letter_stream = redis::key(models::redis_keys::NOTIFIER_DEAD_LETTER_STREAM);
+    redis::publish_to_stream(&dead_letter_stream, &dead_letter, &mut kv).await?;
+    Ok(())
+}
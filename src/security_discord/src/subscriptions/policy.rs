@@ -0,0 +1,112 @@
+use security_api::models;
+
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
+
+/// Longest diff excerpt included in a notification, embed descriptions are capped at 4096
+static MAX_DIFF_EXCERPT_CHARACTERS: usize = 1500;
+
+pub async fn policy_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
+    info!("starting subscription");
+    consume_stream(
+        models::redis_keys::POLICY_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
+
+async fn deliver_queue_item(item: models::PolicyQueueItem) -> Result<(), anyhow::Error> {
+    debug!("policy: recieved message {:#?}", item);
+    info!(
+        "policy: new queue item (id = {}, team = {})",
+        item.id.clone().unwrap(),
+        item.team_handle
+    );
+
+    let delivered = json_webhook::deliver(EventKind::Policy, item.id.as_deref(), &item).await;
+
+    let notification = build_notification(&item).team_handle(Some(&item.team_handle));
+    let notified = notifier::deliver(EventKind::Policy, vec![notification]).await;
+    notifier::combine(vec![delivered, notified])
+}
+
+fn build_notification(item: &models::PolicyQueueItem) -> Notification {
+    let (added, removed) = count_changed_lines(&item.diff);
+    let text = RichText::new()
+        .profile(&item.team_handle)
+        .text(" updated their policy ")
+        .bold(&format!("(+{} -{} lines)", added, removed))
+        .diff(&excerpt(&item.diff));
+
+    Notification::new(models::embed_colors::INFORMAL)
+        .title(
+            "Policy changed",
+            &format!(
+                "https://hackerone.com/{}?view_policy=true",
+                item.team_handle
+            ),
+        )
+        .description(text)
+        .footer(&format!(
+            "{} -> {}",
+            short_hash(&item.old_hash),
+            short_hash(&item.new_hash)
+        ))
+}
+
+fn short_hash(hash: &str) -> &str {
+    hash.get(..7).unwrap_or(hash)
+}
+
+fn count_changed_lines(diff: &str) -> (usize, usize) {
+    let mut added = 0;
+    let mut removed = 0;
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            continue;
+        }
+
+        if line.starts_with('+') {
+            added += 1;
+        } else if line.starts_with('-') {
+            removed += 1;
+        }
+    }
+
+    (added, removed)
+}
+
+/// The start of the diff, cut at a line boundary
+fn excerpt(diff: &str) -> String {
+    let mut excerpt = String::new();
+    for line in diff.lines() {
+        if excerpt.chars().count() + line.chars().count() + 1 > MAX_DIFF_EXCERPT_CHARACTERS {
+            excerpt.push_str("...");
+            return excerpt;
+        }
+
+        excerpt.push_str(line);
+        excerpt.push('\n');
+    }
+
+    String::from(excerpt.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_summarize_diff() {
+        let diff = "@@ -1,3 +1,3 @@\n Scope\n-Critical: 5000\n+Critical: 10000\n+New line\n";
+        assert_eq!(count_changed_lines(diff), (2, 1));
+        assert_eq!(excerpt(diff), diff.trim_end());
+
+        let long_diff = "+line\n".repeat(1000);
+        let long_excerpt = excerpt(&long_diff);
+        assert!(long_excerpt.len() <= MAX_DIFF_EXCERPT_CHARACTERS + 3);
+        assert!(long_excerpt.ends_with("..."));
+    }
+}
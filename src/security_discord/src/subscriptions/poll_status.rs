@@ -0,0 +1,91 @@
+use security_api::models::{self, PollStatus};
+
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
+
+/// Longest error included in a notification
+static MAX_ERROR_CHARACTERS: usize = 1000;
+
+pub async fn poll_status_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
+    info!("starting subscription");
+    consume_stream(
+        models::redis_keys::POLL_STATUS_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
+
+async fn deliver_queue_item(item: models::PollStatusQueueItem) -> Result<(), anyhow::Error> {
+    debug!("poll status: recieved message {:#?}", item);
+    info!(
+        "poll status: new queue item (id = {}, poll = {}, status = {:?})",
+        item.id.clone().unwrap(),
+        item.poll,
+        item.status
+    );
+
+    let delivered = json_webhook::deliver(EventKind::PollStatus, item.id.as_deref(), &item).await;
+    let notified = notifier::deliver(EventKind::PollStatus, vec![build_notification(&item)]).await;
+    notifier::combine(vec![delivered, notified])
+}
+
+fn build_notification(item: &models::PollStatusQueueItem) -> Notification {
+    let (title, color, text) = match item.status {
+        PollStatus::Degraded => (
+            "Poll degraded",
+            models::embed_colors::NEGATIVE,
+            RichText::new().text("The ").bold(&item.poll).text(&format!(
+                " poll failed {} times in a row, it is retried hourly until it succeeds",
+                item.consecutive_failures
+            )),
+        ),
+        PollStatus::Recovered => (
+            "Poll recovered",
+            models::embed_colors::POSTIVE,
+            RichText::new().text("The ").bold(&item.poll).text(&format!(
+                " poll succeeded again after {} failures",
+                item.consecutive_failures
+            )),
+        ),
+    };
+
+    let mut notification = Notification::new(color).description(text);
+    notification.title = Some(String::from(title));
+    if let Some(error) = &item.error {
+        let error: String = error.chars().take(MAX_ERROR_CHARACTERS).collect();
+        notification = notification.field("Error", RichText::new().text(&error), false);
+    }
+
+    notification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_degraded_polls() {
+        let item = models::PollStatusQueueItem {
+            id: Some(String::from("id")),
+            poll: String::from("reports"),
+            status: PollStatus::Degraded,
+            consecutive_failures: 5,
+            error: Some("x".repeat(2000)),
+            created_at: Default::default(),
+        };
+
+        let notification = build_notification(&item);
+        assert_eq!(notification.title.as_deref(), Some("Poll degraded"));
+        assert_eq!(notification.url, None);
+        assert_eq!(notification.fields.len(), 1);
+
+        let description = notification
+            .description
+            .unwrap()
+            .render(|span| format!("{span:?}"));
+        assert!(description.contains("reports"));
+        assert!(description.contains("5 times"));
+    }
+}
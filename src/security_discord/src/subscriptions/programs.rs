@@ -0,0 +1,55 @@
+use security_api::models;
+
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
+
+pub async fn programs_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
+    info!("starting subscription");
+    consume_stream(
+        models::redis_keys::PROGRAMS_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
+
+async fn deliver_queue_item(item: models::ProgramsQueueItem) -> Result<(), anyhow::Error> {
+    debug!("programs: recieved message {:#?}", item);
+    info!(
+        "programs: new queue items (id = {}, launched = {}, retired = {})",
+        item.id.clone().unwrap(),
+        item.launched.len(),
+        item.retired.len()
+    );
+
+    let delivered = json_webhook::deliver(EventKind::Programs, item.id.as_deref(), &item).await;
+
+    let launched = item.launched.iter().map(|program| build_notification(program, true));
+    let retired = item.retired.iter().map(|program| build_notification(program, false));
+    let notified = notifier::deliver(EventKind::Programs, launched.chain(retired).collect()).await;
+    notifier::combine(vec![delivered, notified])
+}
+
+fn build_notification(program: &models::ProgramData, launched: bool) -> Notification {
+    let url = format!("https://hackerone.com/{}", program.handle);
+    let mut text = RichText::new().profile(&program.handle);
+    let color = if launched {
+        let kind = if program.offers_bounties {
+            "bug bounty program"
+        } else {
+            "vulnerability disclosure program"
+        };
+
+        text = text.text(" launched a public ").bold(kind);
+        models::embed_colors::POSTIVE
+    } else {
+        text = text.text(" is no longer listed on HackerOne");
+        models::embed_colors::NEGATIVE
+    };
+
+    Notification::new(color)
+        .title(&program.name, &url)
+        .description(text)
+        .team_handle(Some(&program.handle))
+}
@@ -1,112 +1,260 @@
-use futures_util::StreamExt;
-use security_api::models;
-use security_api::redis;
-use twilight_model::channel::message::embed::Embed;
-use twilight_util::builder::embed::EmbedBuilder;
-use twilight_util::builder::embed::EmbedFieldBuilder;
+use security_api::models::{self, ReportChange};
 
-use crate::webhook;
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
 
-pub async fn reports_subscription() -> Result<(), anyhow::Error> {
+static CVE_REFERENCE_URL: &str = "https://www.cve.org/CVERecord?id=";
+
+pub async fn reports_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
     info!("starting subscription");
+    consume_stream(
+        models::redis_keys::REPORTS_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
 
-    let kv_config = redis::get_config();
-    let kv_config = kv_config.url.unwrap();
-    let redis = redis::redis::Client::open(kv_config)?;
-    let mut pubsub = redis.get_async_pubsub().await?;
-    pubsub
-        .subscribe(models::redis_keys::REPORTS_QUEUE_PUBSUB)
-        .await?;
-
-    let mut stream = pubsub.into_on_message();
-    while let Some(message) = stream.next().await {
-        let payload: String = message.get_payload().unwrap();
-        let decoded: models::ReportsDataQueueItem = serde_json::from_str(&payload).unwrap();
-        debug!("reports: recieved message {:#?}", decoded);
-        info!(
-            "reports: new queue items (id = {}, items = {})",
-            decoded.id.clone().unwrap(),
-            decoded.diff.len()
-        );
+async fn deliver_queue_item(decoded: models::ReportsDataQueueItem) -> Result<(), anyhow::Error> {
+    debug!("reports: recieved message {:#?}", decoded);
+    info!(
+        "reports: new queue items (id = {}, items = {})",
+        decoded.id.clone().unwrap(),
+        decoded.diff.len()
+    );
 
-        for diff in decoded.diff {
-            let embed = build_embed_data(diff);
-            if let Some(embed) = embed {
-                webhook::deliver_embeds(vec![embed]).await?;
-            }
+    let delivered = json_webhook::deliver(EventKind::Reports, decoded.id.as_deref(), &decoded).await;
+
+    let mut notifications = vec![];
+    for diff in &decoded.diff {
+        // reports from all programs carry their own team handle
+        let team_handle = diff[1].team_handle.as_deref().or(decoded.team_handle.as_deref());
+        for notification in build_notifications(diff) {
+            notifications.push(notification.team_handle(team_handle));
         }
     }
 
-    Ok(())
+    let notified = notifier::deliver(EventKind::Reports, notifications).await;
+    notifier::combine(vec![delivered, notified])
 }
 
-fn build_embed_data(diff: Vec<models::ReportData>) -> Option<Embed> {
+fn build_notifications(diff: &[models::ReportData]) -> Vec<Notification> {
     if diff.len() < 2 {
         panic!("invalid diff data");
     }
 
-    let _old = &diff[0];
+    let old = &diff[0];
     let new = &diff[1];
 
-    // tracks disclosed reports
-    if new.disclosed {
-        // report closed (undisclosed)
-        let mut user_field = format!(
-            "[**``{}``**]({})",
-            new.user_name,
-            format!("https://hackerone.com/{}", new.user_name)
-        );
+    let title = new
+        .title
+        .clone()
+        .unwrap_or(String::from("(unknown title)"));
+    let url = new
+        .url
+        .clone()
+        .unwrap_or(String::from("https://hackerone.com/???"));
 
-        if new.collaboration {
-            user_field = format!("{} (+ unknown collaborator)", user_field);
-        }
+    let mut notifications = vec![];
+    for change in old.changes(new) {
+        let notification = match change {
+            // tracks disclosed reports
+            ReportChange::Disclosed => build_disclosed_notification(new, &title, &url),
+            ReportChange::BountyAwarded { old, new: awarded } => {
+                let mut bounty = format!("{} {}", awarded, new.currency);
+                if old > 0.0 {
+                    bounty = format!("{} {} → {}", old, new.currency, bounty);
+                }
 
-        let title = new
-            .title
-            .clone()
-            .unwrap_or(String::from("(unknown title)"))
-            .to_string();
-        let summary = new.summary.clone();
-        let url = new
-            .url
-            .clone()
-            .unwrap_or(String::from("https://hackerone.com/???"));
-        let severity = new
-            .severity
-            .clone()
-            .unwrap_or(String::from("unknown"))
-            .to_string();
-        let bounty = if new.awarded_amount < 0.0 {
-            String::from("hidden")
-        } else {
-            format!("{} {}", new.awarded_amount, new.currency)
+                Notification::new(models::embed_colors::MAJOR)
+                    .title(&title, &url)
+                    .description("Bounty awarded".into())
+                    .field("Reporter", RichText::new().profile(&new.user_name), true)
+                    .field("Bounty Award", bounty.as_str().into(), true)
+            }
+            ReportChange::SeverityRerated { old, new: severity } => {
+                Notification::new(models::embed_colors::INFORMAL)
+                    .title(&title, &url)
+                    .description("Severity re-rated".into())
+                    .field("Reporter", RichText::new().profile(&new.user_name), true)
+                    .field("Severity", format!("{} → {}", old, severity).as_str().into(), true)
+            }
+            ReportChange::CveAssigned(cve_ids) => Notification::new(models::embed_colors::POSTIVE)
+                .title(&title, &url)
+                .description("CVE assigned".into())
+                .field("Reporter", RichText::new().profile(&new.user_name), true)
+                .field("CVE", cve_links(&cve_ids), true),
+            ReportChange::SubstateChanged { old, new: substate } => {
+                Notification::new(models::embed_colors::INFORMAL)
+                    .title(&title, &url)
+                    .description("Report state changed".into())
+                    .field("Reporter", RichText::new().profile(&new.user_name), true)
+                    .field("State", format!("{} → {}", old, substate).as_str().into(), true)
+            }
         };
 
-        let mut embed = EmbedBuilder::new()
-            .color(models::embed_colors::TRANSPARENT)
-            .title(title)
-            .url(url)
-            .field(EmbedFieldBuilder::new("Reporter", user_field).build());
+        notifications.push(program_details(notification, new));
+    }
 
-        if summary.is_some() {
-            embed = embed.field(EmbedFieldBuilder::new("Summary", summary.unwrap()).build())
-        }
+    notifications
+}
+
+/// Shows which program the report is from, reports from all programs would be
+/// indistinguishable otherwise
+fn program_details(mut notification: Notification, report: &models::ReportData) -> Notification {
+    if let Some(team_name) = &report.team_name {
+        notification = notification.footer(team_name);
+    }
+
+    if let Some(url) = &report.team_profile_picture_url {
+        notification = notification.thumbnail(url);
+    }
+
+    notification
+}
+
+fn cve_links(cve_ids: &[String]) -> RichText {
+    let links = cve_ids
+        .iter()
+        .map(|id| RichText::new().link(id, &format!("{}{}", CVE_REFERENCE_URL, id)))
+        .collect();
+
+    RichText::join(links)
+}
+
+/// Dates are rendered without the time, e.g. "2024-05-01T12:00:00.000Z" as "2024-05-01"
+fn format_date(date: &str) -> &str {
+    date.get(..10).unwrap_or(date)
+}
+
+fn build_disclosed_notification(new: &models::ReportData, title: &str, url: &str) -> Notification {
+    let mut reporter = RichText::new().profile(&new.user_name);
+    if new.collaboration {
+        reporter = reporter.text(" (+ unknown collaborator)");
+    }
+
+    let severity = new
+        .severity
+        .clone()
+        .unwrap_or(String::from("unknown"));
+    let bounty = if new.awarded_amount < 0.0 {
+        String::from("hidden")
+    } else {
+        format!("{} {}", new.awarded_amount, new.currency)
+    };
+
+    let mut notification = Notification::new(models::embed_colors::TRANSPARENT)
+        .title(title, url)
+        .field("Reporter", reporter, false);
 
-        embed = embed
-            .field(
-                EmbedFieldBuilder::new("Severity", severity)
-                    .inline()
-                    .build(),
-            )
-            .field(
-                EmbedFieldBuilder::new("Bounty Award", bounty)
-                    .inline()
-                    .build(),
-            );
-
-        let embed = embed.build();
-        return Some(embed);
+    if let Some(summary) = &new.summary {
+        notification = notification.field("Summary", summary.as_str().into(), false);
     }
 
-    None
+    notification = notification
+        .field("Severity", severity.as_str().into(), true)
+        .field("Bounty Award", bounty.as_str().into(), true);
+
+    if let Some(cwe) = new.cwe.as_deref().filter(|cwe| !cwe.is_empty()) {
+        notification = notification.field("Weakness", cwe.into(), true);
+    }
+
+    if let Some(cve_ids) = new.cve_ids.as_deref().filter(|ids| !ids.is_empty()) {
+        notification = notification.field("CVE", cve_links(cve_ids), true);
+    }
+
+    if let Some(submitted_at) = &new.submitted_at {
+        notification = notification.field("Submitted", format_date(submitted_at).into(), true);
+    }
+
+    if let Some(disclosed_at) = &new.disclosed_at {
+        notification = notification.field("Disclosed", format_date(disclosed_at).into(), true);
+    }
+
+    notification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announces_changes_of_disclosed_reports() {
+        let old = models::ReportData {
+            id: Some(String::from("1")),
+            disclosed: true,
+            awarded_amount: 500.0,
+            currency: String::from("USD"),
+            severity: Some(String::from("medium")),
+            cve_ids: Some(vec![]),
+            votes: Some(3),
+            ..Default::default()
+        };
+        let new = models::ReportData {
+            awarded_amount: 1500.0,
+            severity: Some(String::from("high")),
+            cve_ids: Some(vec![String::from("CVE-2024-0001")]),
+            votes: Some(10),
+            ..old.clone()
+        };
+
+        let notifications = build_notifications(&[old.clone(), new.clone()]);
+        let colors: Vec<u32> = notifications.iter().map(|n| n.color).collect();
+        assert_eq!(
+            colors,
+            vec![
+                models::embed_colors::MAJOR,
+                models::embed_colors::INFORMAL,
+                models::embed_colors::POSTIVE
+            ]
+        );
+        assert_eq!(notifications[0].fields[1].value, "500 USD → 1500 USD".into());
+
+        // vote counts alone aren't announced
+        let voted = models::ReportData { votes: Some(20), ..old.clone() };
+        assert!(build_notifications(&[old, voted]).is_empty());
+
+        // fields saved before they were tracked aren't reported as changed
+        let legacy = models::ReportData { cve_ids: None, ..new.clone() };
+        assert!(build_notifications(&[legacy, new]).is_empty());
+    }
+
+    #[test]
+    fn renders_report_details() {
+        let report = models::ReportData {
+            disclosed: true,
+            cwe: Some(String::from("Cross-site Scripting (XSS) - Stored")),
+            cve_ids: Some(vec![String::from("CVE-2024-0001")]),
+            submitted_at: Some(String::from("2024-05-01T12:00:00.000Z")),
+            team_name: Some(String::from("Security")),
+            team_profile_picture_url: Some(String::from("https://example.com/avatar.png")),
+            ..Default::default()
+        };
+
+        let notifications = build_notifications(&[models::ReportData::default(), report]);
+        let notification = &notifications[0];
+        let field = |name: &str| {
+            notification
+                .fields
+                .iter()
+                .find(|field| field.name == name)
+                .map(|field| field.value.clone())
+        };
+
+        assert_eq!(
+            field("CVE"),
+            Some(RichText::new().link(
+                "CVE-2024-0001",
+                "https://www.cve.org/CVERecord?id=CVE-2024-0001"
+            ))
+        );
+        assert_eq!(field("Submitted"), Some("2024-05-01".into()));
+        assert_eq!(field("Disclosed"), None);
+        assert_eq!(notification.footer.as_deref(), Some("Security"));
+        assert_eq!(
+            notification.thumbnail_url.as_deref(),
+            Some("https://example.com/avatar.png")
+        );
+    }
 }
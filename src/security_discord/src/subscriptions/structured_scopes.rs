@@ -0,0 +1,189 @@
+use security_api::models;
+
+use super::consume_stream;
+use crate::json_webhook;
+use crate::notifier::{self, EventKind, Notification, RichText};
+
+pub async fn structured_scopes_subscription(consumer_name: &str) -> Result<(), anyhow::Error> {
+    info!("starting subscription");
+    consume_stream(
+        models::redis_keys::STRUCTURED_SCOPES_QUEUE_STREAM,
+        consumer_name,
+        deliver_queue_item,
+    )
+    .await
+}
+
+async fn deliver_queue_item(item: models::ScopeDataQueueItem) -> Result<(), anyhow::Error> {
+    debug!("structured scopes: recieved message {:#?}", item);
+    info!(
+        "structured scopes: new queue items (id = {}, items = {})",
+        item.id.clone().unwrap(),
+        item.diff.len()
+    );
+
+    let delivered = json_webhook::deliver(EventKind::Scopes, item.id.as_deref(), &item).await;
+
+    let notifications = item
+        .diff
+        .iter()
+        .filter_map(|diff| build_notification(diff, &item.team_handle))
+        .map(|notification| notification.team_handle(Some(&item.team_handle)))
+        .collect();
+
+    let notified = notifier::deliver(EventKind::Scopes, notifications).await;
+    notifier::combine(vec![delivered, notified])
+}
+
+fn scope_name(scope: &models::ScopeData) -> &str {
+    if scope.eligible_for_submission {
+        "scope"
+    } else {
+        "out of scope"
+    }
+}
+
+fn build_notification(diff: &[models::ScopeData], team_handle: &str) -> Option<Notification> {
+    if diff.len() < 2 {
+        panic!("invalid diff data");
+    }
+
+    let old = &diff[0];
+    let new = &diff[1];
+    let program = RichText::new().profile(team_handle);
+
+    if old.id.is_empty() {
+        // asset added
+        let text = program
+            .text(" added ")
+            .bold(&new.asset_identifier)
+            .text(&format!(" to {}", scope_name(new)));
+
+        let color = if new.eligible_for_submission {
+            models::embed_colors::POSTIVE
+        } else {
+            models::embed_colors::INFORMAL
+        };
+
+        return Some(with_asset_fields(
+            Notification::new(color).description(text),
+            new,
+        ));
+    } else if new.id.is_empty() {
+        // asset removed
+        let text = program
+            .text(" removed ")
+            .bold(&old.asset_identifier)
+            .text(&format!(" from {}", scope_name(old)));
+
+        return Some(Notification::new(models::embed_colors::NEGATIVE).description(text));
+    }
+
+    let mut changes = vec![];
+    if old.eligible_for_submission != new.eligible_for_submission {
+        changes.push(format!(
+            "moved from {} to {}",
+            scope_name(old),
+            scope_name(new)
+        ));
+    }
+
+    if old.eligible_for_bounty != new.eligible_for_bounty {
+        changes.push(String::from(if new.eligible_for_bounty {
+            "is now eligible for bounty"
+        } else {
+            "is no longer eligible for bounty"
+        }));
+    }
+
+    if old.max_severity != new.max_severity {
+        changes.push(format!(
+            "max severity changed from {} to {}",
+            old.max_severity.as_deref().unwrap_or("none"),
+            new.max_severity.as_deref().unwrap_or("none")
+        ));
+    }
+
+    if changes.is_empty() {
+        return None;
+    }
+
+    let changes = changes
+        .iter()
+        .map(|change| RichText::from(change.as_str()))
+        .collect();
+    let text = RichText::new()
+        .bold(&new.asset_identifier)
+        .text(" in ")
+        .append(program)
+        .text(" ")
+        .append(RichText::join(changes));
+
+    Some(with_asset_fields(
+        Notification::new(models::embed_colors::MAJOR).description(text),
+        new,
+    ))
+}
+
+fn with_asset_fields(notification: Notification, scope: &models::ScopeData) -> Notification {
+    let bounty = if scope.eligible_for_bounty {
+        "eligible"
+    } else {
+        "ineligible"
+    };
+    let severity = scope.max_severity.as_deref().unwrap_or("none");
+    let asset_type = scope.asset_type.as_deref().unwrap_or("unknown");
+
+    notification
+        .field("Asset Type", asset_type.into(), true)
+        .field("Bounty", bounty.into(), true)
+        .field("Max Severity", severity.into(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope() -> models::ScopeData {
+        models::ScopeData {
+            id: String::from("1"),
+            team_handle: String::from("security"),
+            asset_identifier: String::from("*.example.com"),
+            asset_type: Some(String::from("Wildcard")),
+            eligible_for_submission: true,
+            eligible_for_bounty: true,
+            max_severity: Some(String::from("critical")),
+        }
+    }
+
+    fn describe(notification: Notification) -> String {
+        notification.description.unwrap().render(|span| match span {
+            notifier::Span::Text(text) | notifier::Span::Bold(text) => text.clone(),
+            notifier::Span::Diff(diff) => diff.clone(),
+            notifier::Span::Link { text, .. } => text.clone(),
+        })
+    }
+
+    #[test]
+    fn describes_added_asset() {
+        let notification =
+            build_notification(&[models::ScopeData::default(), scope()], "security").unwrap();
+        assert_eq!(
+            describe(notification),
+            "security added *.example.com to scope"
+        );
+    }
+
+    #[test]
+    fn describes_changed_asset() {
+        let mut changed = scope();
+        changed.eligible_for_bounty = false;
+        changed.max_severity = Some(String::from("high"));
+
+        let notification = build_notification(&[scope(), changed], "security").unwrap();
+        assert_eq!(
+            describe(notification),
+            "*.example.com in security is no longer eligible for bounty and max severity changed from critical to high"
+        );
+    }
+}
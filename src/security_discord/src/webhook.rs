@@ -1,68 +1,340 @@
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 
-use reqwest::Client;
-use serde::Serialize;
-use tokio::sync::RwLock;
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
 use twilight_model::channel::message::Embed;
+use twilight_util::builder::embed::{
+    EmbedBuilder, EmbedFieldBuilder, EmbedFooterBuilder, ImageSource,
+};
+
+use crate::metrics;
+use crate::notifier::{Notification, Rejected, RichText, Sink, Span};
 
 #[derive(Serialize)]
 struct DiscordMessage {
     embeds: Vec<Embed>,
 }
 
-static WEBHOOK_URL: LazyLock<RwLock<String>> = LazyLock::new(|| RwLock::new(String::new()));
 static HTTP_REQUEST_CLIENT: LazyLock<Client> = LazyLock::new(|| {
-    Client::builder()
-        .http2_prior_knowledge()
-        .build()
-        .expect("failed to build request client")
+    let builder = Client::builder();
+    // the stand-in webhooks in tests only speak HTTP/1.1
+    #[cfg(not(test))]
+    let builder = builder.http2_prior_knowledge();
+
+    builder.build().expect("failed to build request client")
 });
 
-pub async fn deliver_embeds(embeds: Vec<Embed>) -> Result<(), anyhow::Error> {
-    let webhook_url = WEBHOOK_URL.read().await;
+static MAX_EMBEDS_PER_MESSAGE: usize = 10;
+static MAX_MESSAGE_CHARACTERS: usize = 6000;
+
+static MAX_TRIES: u32 = 5;
+static RETRY_DELAY: Duration = Duration::from_secs(1);
+
+static SEND_QUEUE: LazyLock<Mutex<RateLimits>> =
+    LazyLock::new(|| Mutex::new(RateLimits::default()));
+
+pub struct DiscordSink {
+    webhook_url: String,
+}
+
+impl DiscordSink {
+    /// Validates that the webhook exists before notifications are sent to it
+    pub async fn new(webhook_url: &str) -> Result<Self, anyhow::Error> {
+        let webhook = extract_webhook_info(webhook_url);
+        if webhook.is_none() {
+            return Err(anyhow::Error::msg("failed to parse webhook, ensure webhook url is format: https://discord.com/api/webhooks/:id/:token"));
+        }
+
+        let (webhook_id, webhook_token) = webhook.unwrap();
+        let webhook_req = reqwest::get(format!(
+            "https://discord.com/api/webhooks/{}/{}",
+            webhook_id, webhook_token
+        ))
+        .await?;
+
+        webhook_req.error_for_status()?;
+        Ok(DiscordSink {
+            webhook_url: String::from(webhook_url),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for DiscordSink {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    async fn deliver(&self, notifications: &[Notification]) -> Result<(), anyhow::Error> {
+        let embeds = notifications.iter().map(build_embed).collect();
+        for batch in batch_embeds(embeds) {
+            deliver_embeds(&self.webhook_url, batch).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Packs embeds, in order, into as few messages as Discord's limits of 10 embeds
+/// and 6000 characters per message allow
+pub fn batch_embeds(embeds: Vec<Embed>) -> Vec<Vec<Embed>> {
+    let mut batches: Vec<Vec<Embed>> = vec![];
+    let mut batch_characters = 0;
+
+    for embed in embeds {
+        let characters = embed_characters(&embed);
+        let fits = batches.last().is_some_and(|batch| {
+            batch.len() < MAX_EMBEDS_PER_MESSAGE
+                && batch_characters + characters <= MAX_MESSAGE_CHARACTERS
+        });
+
+        if fits {
+            batch_characters += characters;
+            batches.last_mut().unwrap().push(embed);
+        } else {
+            batch_characters = characters;
+            batches.push(vec![embed]);
+        }
+    }
+
+    batches
+}
+
+/// Characters Discord counts towards the message total
+fn embed_characters(embed: &Embed) -> usize {
+    let count = |text: &str| text.chars().count();
+
+    embed.title.as_deref().map_or(0, count)
+        + embed.description.as_deref().map_or(0, count)
+        + embed
+            .author
+            .as_ref()
+            .map_or(0, |author| count(&author.name))
+        + embed
+            .footer
+            .as_ref()
+            .map_or(0, |footer| count(&footer.text))
+        + embed
+            .fields
+            .iter()
+            .map(|field| count(&field.name) + count(&field.value))
+            .sum::<usize>()
+}
+
+pub fn build_embed(notification: &Notification) -> Embed {
+    let mut embed = EmbedBuilder::new().color(notification.color);
+
+    if let Some(title) = &notification.title {
+        embed = embed.title(title);
+    }
+
+    if let Some(url) = &notification.url {
+        embed = embed.url(url);
+    }
+
+    if let Some(description) = &notification.description {
+        embed = embed.description(render_markdown(description));
+    }
+
+    for field in &notification.fields {
+        let mut embed_field = EmbedFieldBuilder::new(&field.name, render_markdown(&field.value));
+        if field.inline {
+            embed_field = embed_field.inline();
+        }
+
+        embed = embed.field(embed_field.build());
+    }
+
+    if let Some(footer) = &notification.footer {
+        embed = embed.footer(EmbedFooterBuilder::new(footer));
+    }
+
+    // an invalid image url shouldn't keep the rest of the embed from being sent
+    if let Some(image) = notification
+        .thumbnail_url
+        .as_deref()
+        .and_then(|url| ImageSource::url(url).ok())
+    {
+        embed = embed.thumbnail(image);
+    }
+
+    embed.build()
+}
+
+fn render_markdown(text: &RichText) -> String {
+    text.render(|span| match span {
+        Span::Text(text) => text.clone(),
+        Span::Bold(text) => format!("**{}**", text),
+        // a zero width space keeps backticks in the policy from closing the block
+        Span::Diff(diff) => format!("\n```diff\n{}\n```", diff.replace("```", "`\u{200b}``")),
+        Span::Link { text, url } => format!("[**``{}``**]({})", text, url),
+    })
+}
+
+/// Sends one message, waiting out rate limits and retrying network errors and 5xx responses.
+/// Other responses fail with [`Rejected`] since they won't change when retried.
+/// Every delivery holds the send queue until it's done, so bursts from all subscriptions are
+/// paced one message at a time instead of racing each other into 429s.
+pub async fn deliver_embeds(webhook_url: &str, embeds: Vec<Embed>) -> Result<(), anyhow::Error> {
     let message = DiscordMessage { embeds };
     trace!("sending embed: {:#?}", message.embeds);
-    let mut tries = 0;
 
+    let mut rate_limits = SEND_QUEUE.lock().await;
+    let mut tries = 0;
     loop {
-        if tries >= 5 {
-            return Err(anyhow::Error::msg("failed to deliver embeds (5 tries)"));
+        if let Some(delay) = rate_limits.delay(webhook_url, Instant::now()) {
+            debug!("waiting {:?} for discord rate limit", delay);
+            tokio::time::sleep(delay).await;
         }
 
+        metrics::record_attempt("discord");
         let client_post_result = HTTP_REQUEST_CLIENT
-            .post(&*webhook_url)
+            .post(webhook_url)
             .json(&message)
             .send()
             .await;
 
-        tries += 1;
-        if client_post_result.is_ok() {
-            break;
+        let response = match client_post_result {
+            Ok(response) => response,
+            Err(err) => {
+                tries += 1;
+                error!("webhook failed {}", err);
+                retry_after_failure(tries, "network").await?;
+                continue;
+            }
+        };
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        rate_limits.update(webhook_url, &headers, Instant::now());
+
+        if status.is_success() {
+            return Ok(());
         }
 
-        error!("webhook failed {}", client_post_result.err().unwrap());
+        let body = response.text().await.unwrap_or_default();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            // rate limited requests are waited out and don't count as failed tries
+            let retry_after =
+                rate_limits.rate_limited(webhook_url, &headers, &body, Instant::now());
+            warn!("webhook rate limited, retrying after {:?}", retry_after);
+            metrics::record_retry("discord", "rate_limited");
+        } else if status.is_server_error() {
+            tries += 1;
+            error!("webhook failed {}: {}", status, body);
+            retry_after_failure(tries, "server_error").await?;
+        } else {
+            // sending the same message again won't change the response
+            metrics::record_failure("discord");
+            return Err(Rejected(format!("webhook rejected message {}: {}", status, body)).into());
+        }
     }
+}
 
+async fn retry_after_failure(tries: u32, reason: &str) -> Result<(), anyhow::Error> {
+    if tries >= MAX_TRIES {
+        metrics::record_failure("discord");
+        return Err(anyhow::Error::msg(format!(
+            "failed to deliver embeds ({} tries)",
+            MAX_TRIES
+        )));
+    }
+
+    metrics::record_retry("discord", reason);
+    tokio::time::sleep(RETRY_DELAY * 2u32.pow(tries - 1)).await;
     Ok(())
 }
 
-pub async fn set_webhook_url(webhook_url: &str) -> Result<(), anyhow::Error> {
-    let webhook = extract_webhook_info(webhook_url);
-    if webhook.is_none() {
-        return Err(anyhow::Error::msg("failed to parse webhook, ensure webhook url is format: https://discord.com/api/webhooks/:id/:token"));
+/// Discord's rate limit state, learned from the `X-RateLimit-*` headers of every response
+#[derive(Debug, Default)]
+struct RateLimits {
+    /// The bucket every webhook url was last seen in, webhooks can share a bucket
+    buckets: HashMap<String, String>,
+    /// When exhausted buckets have requests available again
+    reset_at: HashMap<String, Instant>,
+    global_reset_at: Option<Instant>,
+}
+
+impl RateLimits {
+    /// How long to wait before sending to `webhook_url`, if at all
+    fn delay(&self, webhook_url: &str, now: Instant) -> Option<Duration> {
+        let bucket_reset_at = self
+            .buckets
+            .get(webhook_url)
+            .and_then(|bucket| self.reset_at.get(bucket));
+
+        [self.global_reset_at.as_ref(), bucket_reset_at]
+            .into_iter()
+            .flatten()
+            .filter(|reset_at| **reset_at > now)
+            .max()
+            .map(|reset_at| *reset_at - now)
     }
 
-    let (webhook_id, webhook_token) = webhook.unwrap();
-    let webhook_req = reqwest::get(format!(
-        "https://discord.com/api/webhooks/{}/{}",
-        webhook_id, webhook_token
-    ))
-    .await?;
+    fn update(&mut self, webhook_url: &str, headers: &HeaderMap, now: Instant) {
+        let Some(bucket) = header::<String>(headers, "x-ratelimit-bucket") else {
+            return;
+        };
 
-    webhook_req.error_for_status()?;
-    let mut static_webhook_url = WEBHOOK_URL.write().await;
-    *static_webhook_url = String::from(webhook_url);
-    Ok(())
+        let remaining = header::<u32>(headers, "x-ratelimit-remaining");
+        let reset_after = header::<f64>(headers, "x-ratelimit-reset-after");
+        match (remaining, reset_after) {
+            (Some(0), Some(reset_after)) => {
+                self.reset_at
+                    .insert(bucket.clone(), now + Duration::from_secs_f64(reset_after));
+            }
+            _ => {
+                self.reset_at.remove(&bucket);
+            }
+        }
+
+        self.buckets.insert(String::from(webhook_url), bucket);
+    }
+
+    /// Records a 429 response and returns how long Discord asked to wait
+    fn rate_limited(
+        &mut self,
+        webhook_url: &str,
+        headers: &HeaderMap,
+        body: &str,
+        now: Instant,
+    ) -> Duration {
+        // the body has the precise value, the header is rounded up to whole seconds
+        let retry_after = serde_json::from_str::<RateLimitedBody>(body)
+            .map(|body| body.retry_after)
+            .ok()
+            .or_else(|| header::<f64>(headers, "retry-after"))
+            .map(Duration::from_secs_f64)
+            .unwrap_or(RETRY_DELAY);
+
+        let global = header::<bool>(headers, "x-ratelimit-global").unwrap_or(false);
+        if global {
+            self.global_reset_at = Some(now + retry_after);
+        } else {
+            let bucket = self
+                .buckets
+                .entry(String::from(webhook_url))
+                .or_insert_with(|| String::from(webhook_url));
+            self.reset_at.insert(bucket.clone(), now + retry_after);
+        }
+
+        retry_after
+    }
+}
+
+#[derive(Deserialize)]
+struct RateLimitedBody {
+    retry_after: f64,
+}
+
+fn header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
 }
 
 fn extract_webhook_info(url: &str) -> Option<(u64, &str)> {
@@ -76,3 +348,149 @@ fn extract_webhook_info(url: &str) -> Option<(u64, &str)> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier;
+    use crate::stand_in;
+
+    #[tokio::test]
+    async fn deleted_webhook_rejects_message() {
+        let mut server = stand_in::spawn(vec![stand_in::Response {
+            status: 404,
+            headers: vec![],
+            body: String::from(r#"{"message": "Unknown Webhook", "code": 10015}"#),
+        }])
+        .await;
+
+        let embed = EmbedBuilder::new().title("title").build();
+        let err = deliver_embeds(&server.url, vec![embed]).await.unwrap_err();
+        assert!(notifier::is_rejected(&err));
+        assert!(err.to_string().contains("Unknown Webhook"));
+
+        // not retried
+        server.next_request().await;
+        let retried = tokio::time::timeout(Duration::from_millis(200), server.next_request());
+        assert!(retried.await.is_err());
+    }
+
+    #[test]
+    fn can_render_notification() {
+        let notification = Notification::new(1)
+            .title("title", "https://hackerone.com/reports/1")
+            .description(
+                RichText::new()
+                    .profile("user")
+                    .text(" gained ")
+                    .bold("+7 reputation"),
+            )
+            .field("Severity", "high".into(), true)
+            .footer("Triage");
+
+        let embed = build_embed(&notification);
+        assert_eq!(
+            embed.description.unwrap(),
+            "[**``user``**](https://hackerone.com/user) gained **+7 reputation**"
+        );
+        assert_eq!(embed.url.unwrap(), "https://hackerone.com/reports/1");
+        assert_eq!(embed.fields[0].value, "high");
+        assert!(embed.fields[0].inline);
+        assert_eq!(embed.footer.unwrap().text, "Triage");
+    }
+
+    #[test]
+    fn batches_embeds_in_order() {
+        let embeds = (0..25)
+            .map(|index| EmbedBuilder::new().title(index.to_string()).build())
+            .collect();
+
+        let batches = batch_embeds(embeds);
+        let sizes: Vec<usize> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(sizes, vec![10, 10, 5]);
+        assert_eq!(batches[1][0].title.as_deref(), Some("10"));
+        assert_eq!(batches[2][4].title.as_deref(), Some("24"));
+    }
+
+    #[test]
+    fn batches_stay_under_character_limit() {
+        let embeds = (0..4)
+            .map(|_| EmbedBuilder::new().description("a".repeat(2500)).build())
+            .collect();
+
+        let sizes: Vec<usize> = batch_embeds(embeds)
+            .iter()
+            .map(|batch| batch.len())
+            .collect();
+        assert_eq!(sizes, vec![2, 2]);
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+
+        headers
+    }
+
+    #[test]
+    fn waits_for_exhausted_bucket() {
+        let now = Instant::now();
+        let mut rate_limits = RateLimits::default();
+        rate_limits.update(
+            "a",
+            &headers(&[
+                ("x-ratelimit-bucket", "bucket"),
+                ("x-ratelimit-remaining", "0"),
+                ("x-ratelimit-reset-after", "1.5"),
+            ]),
+            now,
+        );
+
+        assert_eq!(
+            rate_limits.delay("a", now),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(rate_limits.delay("b", now), None);
+
+        rate_limits.update(
+            "a",
+            &headers(&[
+                ("x-ratelimit-bucket", "bucket"),
+                ("x-ratelimit-remaining", "4"),
+            ]),
+            now,
+        );
+        assert_eq!(rate_limits.delay("a", now), None);
+    }
+
+    #[test]
+    fn global_rate_limit_delays_every_webhook() {
+        let now = Instant::now();
+        let mut rate_limits = RateLimits::default();
+        let retry_after = rate_limits.rate_limited(
+            "a",
+            &headers(&[("retry-after", "3"), ("x-ratelimit-global", "true")]),
+            r#"{"message": "You are being rate limited.", "retry_after": 2.5, "global": true}"#,
+            now,
+        );
+
+        assert_eq!(retry_after, Duration::from_millis(2500));
+        assert_eq!(
+            rate_limits.delay("b", now),
+            Some(Duration::from_millis(2500))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_retry_after_header() {
+        let now = Instant::now();
+        let mut rate_limits = RateLimits::default();
+        let retry_after = rate_limits.rate_limited("a", &headers(&[("retry-after", "2")]), "", now);
+
+        assert_eq!(retry_after, Duration::from_secs(2));
+        assert_eq!(rate_limits.delay("a", now), Some(Duration::from_secs(2)));
+        assert_eq!(rate_limits.delay("b", now), None);
+    }
+}
@@ -0,0 +1,49 @@
+extern crate pretty_env_logger;
+#[macro_use]
+extern crate log;
+
+mod routes;
+mod store;
+
+use std::env;
+
+use clap::Parser;
+
+#[derive(Default, Debug, Parser)]
+#[clap(author = "hackermon", version, about)]
+struct Arguments {
+    #[arg(short = 'R', long = "redis", env = "REDIS_URL")]
+    redis: String,
+
+    /// Prefix for every Redis key, must match the poller's namespace
+    #[arg(long = "redis_namespace", env = "REDIS_NAMESPACE", default_value = "")]
+    redis_namespace: String,
+
+    /// Address the API listens on
+    #[arg(
+        long = "listen",
+        env = "LISTEN_ADDRESS",
+        default_value = "0.0.0.0:8080"
+    )]
+    listen: String,
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+
+    let args = Arguments::parse();
+    debug!("{:#?}", args);
+
+    env::set_var("REDIS_URL", &args.redis);
+    env::set_var("REDIS_NAMESPACE", &args.redis_namespace);
+
+    let listener = tokio::net::TcpListener::bind(&args.listen)
+        .await
+        .expect("failed to bind listen address");
+
+    info!("listening on {}", args.listen);
+    axum::serve(listener, routes::router())
+        .await
+        .expect("http server failed");
+}
@@ -0,0 +1,311 @@
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use security_api::models::{RepData, ReportData, ReputationPoint, UserThanksData};
+use security_api::{redis, reputation_history};
+
+use crate::store::{self, ReputationChange};
+
+static DEFAULT_LIMIT: usize = 50;
+static MAX_LIMIT: usize = 500;
+static DEFAULT_HISTORY_DAYS: i64 = 90;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/programs/{handle}/leaderboard", get(leaderboard))
+        .route("/reputation/changes", get(reputation_changes))
+        .route("/reports", get(reports))
+        .route("/researchers/{user}/reputation", get(researcher_reputation))
+        .route("/researchers/{user}/thanks", get(researcher_thanks))
+}
+
+/// Errors are reported with a message in the body
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is invalid, reported as a 400
+    BadRequest(String),
+    /// Anything else, reported as a 500
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        ApiError::Internal(err.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::Internal(err) => {
+                // the error may reveal how the state is stored, it's only logged
+                error!("request failed: {:#}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    String::from("internal server error"),
+                )
+            }
+        };
+
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Pagination {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Page<T> {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub items: Vec<T>,
+}
+
+impl Pagination {
+    fn page<T>(&self, items: Vec<T>) -> Page<T> {
+        let offset = self.offset.unwrap_or(0);
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+        Page {
+            total: items.len(),
+            offset,
+            limit,
+            items: items.into_iter().skip(offset).take(limit).collect(),
+        }
+    }
+}
+
+/// Filters shared by the list endpoints, handles and usernames are matched case-insensitively
+#[derive(Deserialize, Debug, Default)]
+pub struct Filter {
+    program: Option<String>,
+    user: Option<String>,
+    severity: Option<String>,
+    days: Option<i64>,
+}
+
+fn matches(filter: &Option<String>, value: Option<&str>) -> bool {
+    match filter {
+        Some(filter) => value.is_some_and(|value| value.eq_ignore_ascii_case(filter)),
+        None => true,
+    }
+}
+
+/// Leaderboard of a program ordered by rank
+async fn leaderboard(
+    Path(handle): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<Filter>,
+) -> Result<Json<Page<RepData>>, ApiError> {
+    let mut leaderboard: Vec<RepData> = store::leaderboard(&handle)
+        .await?
+        .into_iter()
+        .filter(|rep| matches(&Some(handle.clone()), rep.team_handle.as_deref()))
+        .filter(|rep| matches(&filter.user, Some(&rep.user_name)))
+        .collect();
+
+    leaderboard.sort_by_key(|rep| rep.rank);
+    Ok(Json(pagination.page(leaderboard)))
+}
+
+/// Recent reputation changes, newest first
+async fn reputation_changes(
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<Filter>,
+) -> Result<Json<Page<ReputationChange>>, ApiError> {
+    let changes: Vec<ReputationChange> = store::reputation_changes()
+        .await?
+        .into_iter()
+        .filter(|change| {
+            [&change.old, &change.new].iter().any(|rep| {
+                rep.as_ref().is_some_and(|rep| {
+                    matches(&filter.program, rep.team_handle.as_deref())
+                        && matches(&filter.user, Some(&rep.user_name))
+                })
+            })
+        })
+        .collect();
+
+    Ok(Json(pagination.page(changes)))
+}
+
+/// Disclosed reports, most recently disclosed first
+async fn reports(
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<Filter>,
+) -> Result<Json<Page<ReportData>>, ApiError> {
+    let reports: Vec<ReportData> = store::reports(filter.program.as_deref())
+        .await?
+        .into_iter()
+        .filter(|report| filter_report(&filter, report))
+        .collect();
+
+    Ok(Json(pagination.page(reports)))
+}
+
+fn filter_report(filter: &Filter, report: &ReportData) -> bool {
+    matches(&filter.program, report.team_handle.as_deref())
+        && matches(&filter.user, Some(&report.user_name))
+        && matches(&filter.severity, report.severity.as_deref())
+}
+
+#[derive(Serialize, Debug)]
+struct ProgramReputation {
+    program: String,
+    points: Vec<ReputationPoint>,
+}
+
+/// Days of reputation history to return, history older than the retention isn't kept so
+/// longer periods are capped to it
+fn history_days(filter: &Filter) -> Result<i64, ApiError> {
+    match filter.days {
+        None => Ok(DEFAULT_HISTORY_DAYS),
+        Some(days) if days < 1 => Err(ApiError::BadRequest(format!(
+            "days has to be at least 1, got {}",
+            days
+        ))),
+        Some(days) => Ok(days.min(reputation_history::RETENTION_DAYS)),
+    }
+}
+
+/// Database id of a researcher given by username or id, usernames are looked up on the
+/// saved leaderboards
+async fn researcher_id(user: &str) -> Result<String, ApiError> {
+    if store::is_user_id(user) {
+        return Ok(String::from(user));
+    }
+
+    let researcher = store::find_researcher(user).await?;
+    Ok(researcher.map_or_else(|| String::from(user), |rep| rep.user_id))
+}
+
+/// Reputation history of a researcher per program over the last `days` (90 by default)
+async fn researcher_reputation(
+    Path(user): Path<String>,
+    Query(filter): Query<Filter>,
+) -> Result<Json<Vec<ProgramReputation>>, ApiError> {
+    let days = history_days(&filter)?;
+    let user_id = researcher_id(&user).await?;
+    let mut kv = redis::get_connection().get().await?;
+    let until = chrono::Utc::now().naive_utc();
+    let since = until - chrono::Duration::days(days);
+
+    let programs = match &filter.program {
+        Some(program) => vec![program.clone()],
+        None => reputation_history::programs(&user_id, &mut kv).await?,
+    };
+
+    let mut history = vec![];
+    for program in programs {
+        let points = reputation_history::query(&program, &user_id, since, until, &mut kv).await?;
+        if !points.is_empty() {
+            history.push(ProgramReputation { program, points });
+        }
+    }
+
+    Ok(Json(history))
+}
+
+/// Thanks data of a watched researcher per program
+async fn researcher_thanks(
+    Path(user): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<Filter>,
+) -> Result<Json<Page<UserThanksData>>, ApiError> {
+    let thanks: Vec<UserThanksData> = store::thanks(&user, filter.program.as_deref())
+        .await?
+        .into_iter()
+        .filter(|thanks| thanks.user_name.eq_ignore_ascii_case(&user) || thanks.user_id == user)
+        .filter(|thanks| matches(&filter.program, Some(&thanks.team_handle)))
+        .collect();
+
+    Ok(Json(pagination.page(thanks)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_are_capped() {
+        let items: Vec<usize> = (0..1000).collect();
+
+        let page = Pagination::default().page(items.clone());
+        assert_eq!(
+            (page.total, page.offset, page.limit),
+            (1000, 0, DEFAULT_LIMIT)
+        );
+        assert_eq!(page.items.len(), DEFAULT_LIMIT);
+
+        let pagination = Pagination {
+            offset: Some(990),
+            limit: Some(5000),
+        };
+        let page = pagination.page(items);
+        assert_eq!(page.limit, MAX_LIMIT);
+        assert_eq!(page.items, (990..1000).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn filters_reports() {
+        let report = ReportData {
+            user_name: String::from("Hackermon"),
+            team_handle: Some(String::from("security")),
+            severity: Some(String::from("high")),
+            ..Default::default()
+        };
+
+        let filter = Filter {
+            program: Some(String::from("Security")),
+            user: Some(String::from("hackermon")),
+            ..Default::default()
+        };
+        assert!(filter_report(&filter, &report));
+
+        let filter = Filter {
+            severity: Some(String::from("critical")),
+            ..Default::default()
+        };
+        assert!(!filter_report(&filter, &report));
+    }
+
+    #[tokio::test]
+    async fn hides_internal_errors() {
+        let response =
+            ApiError::Internal(anyhow::Error::msg("WRONGTYPE reports_poll_state")).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"error":"internal server error"}"#);
+    }
+
+    #[test]
+    fn validates_history_days() {
+        let days = |days| {
+            history_days(&Filter {
+                days,
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(days(None).unwrap(), DEFAULT_HISTORY_DAYS);
+        assert_eq!(days(Some(7)).unwrap(), 7);
+        assert_eq!(
+            days(Some(i64::MAX)).unwrap(),
+            reputation_history::RETENTION_DAYS
+        );
+        assert!(matches!(days(Some(0)), Err(ApiError::BadRequest(_))));
+        assert!(matches!(days(Some(-30)), Err(ApiError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,249 @@
+//! Reads the state the poller keeps in Redis
+use std::collections::HashSet;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use security_api::models::{self, RepData, ReportData, UserThanksData};
+use security_api::redis::{self, redis::AsyncCommands};
+
+/// How many of the latest queue items recent changes are read from
+static RECENT_QUEUE_ITEMS: usize = 1000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReputationChange {
+    /// Id of the queue item the change was published in
+    pub id: String,
+    /// `None` if the researcher was added to the leaderboard
+    pub old: Option<RepData>,
+    /// `None` if the researcher was removed from the leaderboard
+    pub new: Option<RepData>,
+    pub created_at: String,
+}
+
+/// Saved leaderboard of a program, a program may be saved in several scopes
+pub async fn leaderboard(program: &str) -> Result<Vec<RepData>, anyhow::Error> {
+    let mut leaderboard: Vec<RepData> =
+        load_program_sets(models::redis_keys::REPUTATION_QUEUE_LAST_DATA, program).await?;
+
+    leaderboard.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+    leaderboard.dedup_by(|a, b| a.user_id == b.user_id);
+    Ok(leaderboard)
+}
+
+/// A saved leaderboard entry of the researcher with `user_name`, in any program
+pub async fn find_researcher(user_name: &str) -> Result<Option<RepData>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let keys = redis::team_keys(models::redis_keys::REPUTATION_QUEUE_LAST_DATA, &mut kv).await?;
+    let pattern = field_pattern("user_name", user_name)?;
+
+    for key in keys {
+        let mut iter = kv.sscan_match::<_, _, String>(&key, &pattern).await?;
+        if let Some(value) = iter.next_item().await {
+            return Ok(Some(serde_json::from_str(&value)?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reputation changes from the latest queue items, newest first
+pub async fn reputation_changes() -> Result<Vec<ReputationChange>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let stream = redis::key(models::redis_keys::REPUTATION_QUEUE_STREAM);
+    let entries = redis::read_stream_latest(&stream, RECENT_QUEUE_ITEMS, &mut kv).await?;
+
+    let mut changes = vec![];
+    for entry in entries {
+        let Some(payload) = entry.payload else {
+            continue;
+        };
+
+        let queue_item: models::RepDataQueueItem = serde_json::from_str(&payload)?;
+        for diff in queue_item.diff {
+            let [old, new] = <[RepData; 2]>::try_from(diff)
+                .map_err(|_| anyhow::Error::msg("invalid diff data"))?;
+
+            // the missing side of a diff is a placeholder without a user
+            changes.push(ReputationChange {
+                id: entry.id.clone(),
+                old: Some(old).filter(|rep| !rep.user_id.is_empty()),
+                new: Some(new).filter(|rep| !rep.user_id.is_empty()),
+                created_at: queue_item
+                    .created_at
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Saved disclosed reports of a program, or of every scope without one, most recently
+/// disclosed first
+pub async fn reports(program: Option<&str>) -> Result<Vec<ReportData>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let name = models::redis_keys::REPORTS_POLL_STATE;
+    let (keys, pattern) = match program {
+        Some(program) => program_keys(name, program).await?,
+        None => (redis::team_keys(name, &mut kv).await?, None),
+    };
+
+    let mut reports: Vec<ReportData> = vec![];
+    let mut ids = HashSet::new();
+    for key in keys {
+        let values: Vec<String> = match &pattern {
+            Some(pattern) => {
+                let mut values = vec![];
+                let mut iter = kv
+                    .hscan_match::<_, _, (String, String)>(&key, pattern)
+                    .await?;
+                while let Some((_, value)) = iter.next_item().await {
+                    values.push(value);
+                }
+
+                values
+            }
+            None => kv.hvals(&key).await?,
+        };
+
+        for value in values {
+            let report: ReportData = serde_json::from_str(&value)?;
+            if ids.insert(report.id.clone()) {
+                reports.push(report);
+            }
+        }
+    }
+
+    // dates are ISO 8601, so they sort as strings
+    reports.sort_by(|a, b| b.disclosed_at.cmp(&a.disclosed_at));
+    Ok(reports)
+}
+
+/// Saved thanks data of a watched researcher given by username or id, optionally in a
+/// single program
+pub async fn thanks(
+    user: &str,
+    program: Option<&str>,
+) -> Result<Vec<UserThanksData>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let name = models::redis_keys::USER_THANKS_DATA_POLL_LAST_DATA;
+    let keys = match program {
+        Some(program) => program_keys(name, program).await?.0,
+        None => redis::team_keys(name, &mut kv).await?,
+    };
+
+    let field = if is_user_id(user) {
+        "user_id"
+    } else {
+        "user_name"
+    };
+    let pattern = field_pattern(field, user)?;
+    let mut thanks = vec![];
+    for key in keys {
+        thanks.append(&mut scan_set(&key, &pattern).await?);
+    }
+
+    Ok(thanks)
+}
+
+/// Database ids are numeric, usernames can't be
+pub fn is_user_id(user: &str) -> bool {
+    !user.is_empty() && user.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Members of the sets `name` holding `program`, read from the program's own key if it's
+/// tracked or from the members of the other keys that belong to it
+async fn load_program_sets<T: DeserializeOwned>(
+    name: &str,
+    program: &str,
+) -> Result<Vec<T>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let (keys, pattern) = program_keys(name, program).await?;
+
+    let mut items = vec![];
+    for key in keys {
+        match &pattern {
+            Some(pattern) => items.append(&mut scan_set(&key, pattern).await?),
+            None => {
+                for value in redis::load_set_to_vec(&key, &mut kv).await? {
+                    items.push(serde_json::from_str::<T>(&value)?);
+                }
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// The keys `name` holds the state of `program` in, with the pattern its entries match if
+/// the keys are shared with other programs
+async fn program_keys(
+    name: &str,
+    program: &str,
+) -> Result<(Vec<String>, Option<String>), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let program_key = redis::team_key(name, Some(program));
+    let tracked: bool = kv.exists(&program_key).await?;
+    if tracked {
+        return Ok((vec![program_key], None));
+    }
+
+    // programs that aren't tracked are saved in the scope of every program or the watchlist
+    let keys = redis::team_keys(name, &mut kv).await?;
+    Ok((keys, Some(field_pattern("team_handle", program)?)))
+}
+
+/// Members of the set `key` matching `pattern`
+async fn scan_set<T: DeserializeOwned>(key: &str, pattern: &str) -> Result<Vec<T>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let mut values = vec![];
+    let mut iter = kv.sscan_match::<_, _, String>(key, pattern).await?;
+    while let Some(value) = iter.next_item().await {
+        values.push(value);
+    }
+
+    let mut items = vec![];
+    for value in values {
+        items.push(serde_json::from_str::<T>(&value)?);
+    }
+
+    Ok(items)
+}
+
+/// Pattern matching JSON objects with the string `field` set to `value`, ignoring case
+fn field_pattern(field: &str, value: &str) -> Result<String, anyhow::Error> {
+    let value = serde_json::to_string(value)?;
+    let mut pattern = format!("*\"{}\":", field);
+    for c in value.chars() {
+        if c.is_ascii_alphabetic() {
+            pattern.push_str(&format!(
+                "[{}{}]",
+                c.to_ascii_lowercase(),
+                c.to_ascii_uppercase()
+            ));
+        } else {
+            pattern.push_str(&redis::escape_pattern(&c.to_string()));
+        }
+    }
+
+    pattern.push('*');
+    Ok(pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_patterns_ignore_case() {
+        assert_eq!(
+            field_pattern("team_handle", "Se*").unwrap(),
+            r#"*"team_handle":"[sS][eE]\*"*"#
+        );
+        assert!(is_user_id("1337"));
+        assert!(!is_user_id("hackermon"));
+        assert!(!is_user_id(""));
+    }
+}
@@ -3,30 +3,78 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
+mod metrics;
 mod polls;
+mod schedule;
+mod scheduler;
 use std::env;
-use std::time::Duration;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 
 use chrono::Datelike;
 use clap::Parser;
 
 use graphql_client::GraphQLQuery;
 use security_api::hackerone::{self as hackerone, HackerOneClient};
-use tokio::sync::mpsc;
+use security_api::health::{self, Check};
+use security_api::redis;
+use tokio::sync::{mpsc, Mutex};
 
-use crate::polls::PollConfiguration;
+use crate::polls::{PollConfiguration, ReportFilter};
+use crate::schedule::{QuietHours, Schedule};
+use crate::scheduler::Scheduler;
 
-#[derive(Default, Debug, Parser)]
+#[derive(Debug, Parser)]
 #[clap(author = "hackermon", version, about)]
 struct Arguments {
     #[arg(short = 'T', long = "session_token", env = "SESSION_TOKEN")]
     hackerone_session_token: Option<String>,
-    
+
     #[arg(short = 'R', long = "redis", env = "REDIS_URL")]
     redis: String,
 
-    #[arg(short = 'H', long = "handle")]
-    hackerone_handle: Option<String>,
+    /// Prefix for every Redis key and channel, lets several trackers share one Redis instance
+    #[arg(long = "redis_namespace", env = "REDIS_NAMESPACE", default_value = "")]
+    redis_namespace: String,
+
+    /// Move existing un-prefixed keys into `redis_namespace` on startup, skipped once the
+    /// namespace holds poll state. Only enable this on the tracker that wrote those keys.
+    #[arg(long = "migrate_redis_keys", action = clap::ArgAction::Set, default_value = "false")]
+    migrate_redis_keys: bool,
+
+    /// HackerOne team handles to track (comma separated), every program is tracked if empty
+    #[arg(short = 'H', long = "handle", value_delimiter = ',')]
+    hackerone_handles: Vec<String>,
+
+    /// Only report changes for these researchers, usernames or user ids (comma separated)
+    #[arg(long = "watch", value_delimiter = ',')]
+    watchlist: Vec<String>,
+
+    /// Also poll every program a watched researcher appears in, this needs usernames
+    #[arg(long = "watch_all_programs", action = clap::ArgAction::Set, default_value = "false")]
+    watch_all_programs: bool,
+
+    /// Most reports fetched from hacktivity per program and poll, paging stops earlier once
+    /// already seen reports are reached
+    #[arg(long = "max_reports_per_poll", default_value = "100")]
+    max_reports_per_poll: usize,
+
+    /// Only announce disclosed reports rated at least this severity, unrated reports never match
+    #[arg(long = "min_severity", value_parser = ["none", "low", "medium", "high", "critical"])]
+    min_severity: Option<String>,
+
+    /// Only announce disclosed reports awarded at least this bounty
+    #[arg(long = "min_bounty")]
+    min_bounty: Option<f64>,
+
+    /// Only announce disclosed reports with one of these keywords in the title, summary or
+    /// weakness (comma separated)
+    #[arg(long = "keyword", value_delimiter = ',')]
+    keywords: Vec<String>,
+
+    /// Only announce disclosed reports with collaborators
+    #[arg(long = "collaboration_only", action = clap::ArgAction::Set, default_value = "false")]
+    collaboration_only: bool,
 
     #[arg(default_value = "true", long)]
     reputation_polling: bool,
@@ -36,14 +84,76 @@ struct Arguments {
 
     #[arg(default_value = "true", long)]
     leaderboard_invalid_reports_polling: bool,
+
+    /// Announce structured scope changes of the tracked programs
+    #[arg(long = "scope_polling", action = clap::ArgAction::Set, default_value = "true")]
+    scope_polling: bool,
+
+    /// Announce bounty table changes of the tracked programs
+    #[arg(long = "bounty_table_polling", action = clap::ArgAction::Set, default_value = "true")]
+    bounty_table_polling: bool,
+
+    /// Announce policy edits of the tracked programs
+    #[arg(long = "policy_polling", action = clap::ArgAction::Set, default_value = "true")]
+    policy_polling: bool,
+
+    /// How often launched and retired programs are polled when tracking every program: an
+    /// interval (`90s`, `5m`, `12h`, `1d`) or a cron expression in UTC (`*/5 * * * *`)
+    #[arg(long = "programs_schedule", default_value = "12h")]
+    programs_schedule: Schedule,
+
+    /// How often leaderboards are polled, an interval or a cron expression
+    #[arg(long = "reputation_schedule", default_value = "1m")]
+    reputation_schedule: Schedule,
+
+    /// How often hacktivity is polled for disclosed reports, an interval or a cron expression
+    #[arg(long = "reports_schedule", default_value = "5m")]
+    reports_schedule: Schedule,
+
+    /// How often researchers are polled for informative reports, an interval or a cron expression
+    #[arg(long = "informative_reports_schedule", default_value = "30m")]
+    informative_reports_schedule: Schedule,
+
+    /// How often structured scopes are polled, an interval or a cron expression
+    #[arg(long = "scope_schedule", default_value = "30m")]
+    scope_schedule: Schedule,
+
+    /// How often bounty tables are polled, an interval or a cron expression
+    #[arg(long = "bounty_table_schedule", default_value = "30m")]
+    bounty_table_schedule: Schedule,
+
+    /// How often policies are polled, an interval or a cron expression
+    #[arg(long = "policy_schedule", default_value = "1h")]
+    policy_schedule: Schedule,
+
+    /// Longest random delay added to every scheduled poll, spreads the load on HackerOne
+    #[arg(long = "poll_jitter", default_value = "0s", value_parser = schedule::parse_duration)]
+    poll_jitter: Duration,
+
+    /// Daily window (UTC) polls don't run in, e.g. `22:00-06:00`. Polls due inside it run
+    /// once it ends.
+    #[arg(long = "quiet_hours")]
+    quiet_hours: Option<QuietHours>,
+
+    /// Failures in a row after which a poll is announced as degraded and only retried hourly
+    #[arg(long = "max_consecutive_failures", default_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    max_consecutive_failures: u32,
+
+    /// Address Prometheus metrics (`/metrics`) and the health endpoints (`/healthz` and
+    /// `/readyz`) are served on, disabled if empty
+    #[arg(long = "metrics_listen", env = "METRICS_LISTEN", default_value = "0.0.0.0:9090")]
+    metrics_listen: String,
 }
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
-    let args = Arguments::parse();
+    let mut args = Arguments::parse();
+    args.hackerone_handles.retain(|handle| !handle.trim().is_empty());
+    args.watchlist.retain(|researcher| !researcher.trim().is_empty());
+    args.keywords.retain(|keyword| !keyword.trim().is_empty());
     info!("hello world");
-    debug!("hackerone team handle: {:?}", args.hackerone_handle);
+    debug!("hackerone team handles: {:?}", args.hackerone_handles);
     debug!("{:#?}", args);
 
     let session_token = args.hackerone_session_token.clone().unwrap_or("".into());
@@ -58,25 +168,69 @@ async fn main() {
 
     let redis_url = &args.redis;
     env::set_var("REDIS_URL", redis_url);
-    
+    env::set_var("REDIS_NAMESPACE", &args.redis_namespace);
+
+    if args.migrate_redis_keys {
+        let mut kv = redis::get_connection().get().await.unwrap();
+        match redis::migrate_unprefixed_keys(&mut kv).await.unwrap() {
+            Some(migrated) => {
+                info!("migrated {} keys into namespace {:?}", migrated, args.redis_namespace)
+            }
+            None => warn!(
+                "namespace {:?} already holds poll state, not migrating un-prefixed keys",
+                args.redis_namespace
+            ),
+        }
+    }
+
+    // older versions didn't scope the state of a single tracked team to it
+    if let [team_handle] = args.hackerone_handles.as_slice() {
+        let mut kv = redis::get_connection().get().await.unwrap();
+        let migrated = redis::migrate_unscoped_team_keys(team_handle, &mut kv).await.unwrap();
+        if migrated > 0 {
+            info!("moved {} unscoped keys to the state of {}", migrated, team_handle);
+        }
+    }
+
     let mut tasks = vec![];
-    let is_tracking_all_programs = args.hackerone_handle.is_none();
     let config = PollConfiguration {
         hackerone: client,
-        team_handle: args.hackerone_handle,
+        team_handles: args.hackerone_handles,
+        watchlist: args.watchlist,
+        watch_all_programs: args.watch_all_programs,
+        max_reports_per_poll: args.max_reports_per_poll,
+        report_filter: ReportFilter {
+            min_severity: args.min_severity,
+            min_bounty: args.min_bounty,
+            keywords: args.keywords,
+            collaboration_only: args.collaboration_only,
+        },
+    };
+
+    if !args.metrics_listen.is_empty() {
+        let metrics_listen = args.metrics_listen.clone();
+        let client = config.hackerone.clone();
+        let readiness = health::router(move || readiness_checks(client.clone()));
+        let router = security_api::metrics::router().merge(readiness);
+        tokio::spawn(async move {
+            security_api::metrics::serve(&metrics_listen, router).await;
+        });
+    }
+
+    let scheduler = Scheduler {
+        max_consecutive_failures: args.max_consecutive_failures,
+        jitter: args.poll_jitter,
+        quiet_hours: args.quiet_hours,
     };
 
-    if is_tracking_all_programs {
+    if config.is_tracking_all_programs() {
         let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.programs_schedule.clone();
         let program_tracking_task = tokio::spawn(async move {
-            let interval = Duration::from_secs(60 * 60 * 12); // 12 hours
-            loop {
-                if let Err(err) = polls::programs::run_poll(&config).await {
-                    error!("programs: {}", err);
-                }
-
-                tokio::time::sleep(interval).await;
-            }
+            scheduler
+                .run("programs", &schedule, || polls::programs::run_poll(&config))
+                .await;
         });
 
         tasks.push(program_tracking_task);
@@ -84,33 +238,25 @@ async fn main() {
 
     if args.reputation_polling {
         let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.reputation_schedule.clone();
         let leaderboard_tracking_task = tokio::spawn(async move {
-            let interval = Duration::from_secs(60); // 1 minute
-            loop {
-                if let Err(err) = polls::reputation::run_poll(&config).await {
-                    error!("reputation: {}", err);
-                    break;
-                }
-
-                tokio::time::sleep(interval).await;
-            }
+            scheduler
+                .run("reputation", &schedule, || polls::reputation::run_poll(&config))
+                .await;
         });
 
         tasks.push(leaderboard_tracking_task);
     }
-    
+
     if args.hackactivity_polling {
         let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.reports_schedule.clone();
         let reports_tracking_task = tokio::spawn(async move {
-            let interval = Duration::from_secs(60 * 5); // 5 minutes
-            loop {
-                if let Err(err) = polls::reports::run_poll(&config).await {
-                    error!("reports: {}", err);
-                    break;
-                }
-        
-                tokio::time::sleep(interval).await;
-            }
+            scheduler
+                .run("reports", &schedule, || polls::reports::run_poll(&config))
+                .await;
         });
 
         tasks.push(reports_tracking_task);
@@ -118,22 +264,57 @@ async fn main() {
 
     if args.leaderboard_invalid_reports_polling {
         let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.informative_reports_schedule.clone();
         let informative_reports_task = tokio::spawn(async move {
-            let interval = Duration::from_secs(60 * 30); // 30 minutes
-            loop {
-                if let Err(err) = polls::informative_reports::run_poll(&config).await {
-                    error!("informative reports: {:#?}", err);
-                    break;
-                }
-        
-                tokio::time::sleep(interval).await;
-            }
+            scheduler
+                .run("informative_reports", &schedule, || polls::informative_reports::run_poll(&config))
+                .await;
         });
 
         tasks.push(informative_reports_task);
     }
 
-    // Wait for any task to abort
+    if args.scope_polling && !config.is_tracking_all_programs() {
+        let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.scope_schedule.clone();
+        let structured_scopes_task = tokio::spawn(async move {
+            scheduler
+                .run("structured_scopes", &schedule, || polls::structured_scopes::run_poll(&config))
+                .await;
+        });
+
+        tasks.push(structured_scopes_task);
+    }
+
+    if args.bounty_table_polling && !config.is_tracking_all_programs() {
+        let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.bounty_table_schedule.clone();
+        let bounty_tables_task = tokio::spawn(async move {
+            scheduler
+                .run("bounty_tables", &schedule, || polls::bounty_tables::run_poll(&config))
+                .await;
+        });
+
+        tasks.push(bounty_tables_task);
+    }
+
+    if args.policy_polling && !config.is_tracking_all_programs() {
+        let config = config.clone();
+        let scheduler = scheduler.clone();
+        let schedule = args.policy_schedule.clone();
+        let policy_task = tokio::spawn(async move {
+            scheduler
+                .run("policy", &schedule, || polls::policy::run_poll(&config))
+                .await;
+        });
+
+        tasks.push(policy_task);
+    }
+
+    // Polls never stop, failures and panics are retried by the scheduler
     let (abort_sender, mut abort_receiver) = mpsc::channel(1);
     for task in tasks {
         let sender = abort_sender.clone();
@@ -147,11 +328,39 @@ async fn main() {
     let _ = abort_receiver.recv().await;
 }
 
+/// Session checks are cached so frequent readiness probes don't reach HackerOne every time
+static SESSION_CHECK: LazyLock<Mutex<Option<(Instant, Check)>>> = LazyLock::new(|| Mutex::new(None));
+static SESSION_CHECK_TTL: Duration = Duration::from_secs(60 * 5);
+
+async fn readiness_checks(client: HackerOneClient) -> Vec<Check> {
+    let mut session_check = SESSION_CHECK.lock().await;
+    let cached = session_check
+        .as_ref()
+        .filter(|(checked_at, _)| checked_at.elapsed() < SESSION_CHECK_TTL);
+
+    let hackerone = match cached {
+        Some((_, check)) => check.clone(),
+        None => {
+            let result = match hackerone::is_session_valid(&client).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(anyhow::Error::msg("session token is signed out")),
+                Err(err) => Err(err),
+            };
+
+            let check = Check::new("hackerone", result);
+            *session_check = Some((Instant::now(), check.clone()));
+            check
+        }
+    };
+
+    vec![health::check_redis().await, hackerone]
+}
+
 async fn ensure_args(client: &HackerOneClient, args: &Arguments) -> Result<bool, anyhow::Error> {
     let now = chrono::Utc::now().date_naive();
 
-    // Verify HackerOne handle
-    if let Some(hackerone_handle) = &args.hackerone_handle {
+    // Verify HackerOne handles
+    for hackerone_handle in &args.hackerone_handles {
         let variables = hackerone::team_year_thank_query::Variables {
             selected_handle: hackerone_handle.clone(),
             year: Some(now.year().into()),
@@ -178,7 +387,10 @@ async fn ensure_args(client: &HackerOneClient, args: &Arguments) -> Result<bool,
             .selected_team
             .is_some();
 
-        return Ok(can_fetch_team);
+        if !can_fetch_team {
+            error!("cannot fetch team {}", hackerone_handle);
+            return Ok(false);
+        }
     }
 
     Ok(true)
@@ -0,0 +1,87 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec,
+};
+
+static POLL_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "tracker_poll_duration_seconds",
+        "How long a poll took to run",
+        &["poll"],
+        vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]
+    )
+    .unwrap()
+});
+
+static POLL_RUNS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tracker_poll_runs_total",
+        "Finished poll runs by result (success or failure)",
+        &["poll", "result"]
+    )
+    .unwrap()
+});
+
+static POLL_CHANGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tracker_poll_changes_total",
+        "Changes a poll detected and published",
+        &["poll"]
+    )
+    .unwrap()
+});
+
+static GRAPHQL_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tracker_graphql_errors_total",
+        "HackerOne GraphQL responses with errors",
+        &["query"]
+    )
+    .unwrap()
+});
+
+static POLL_CIRCUIT_OPEN: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "tracker_poll_circuit_open",
+        "Whether a poll failed too often in a row and is only retried after a cooldown",
+        &["poll"]
+    )
+    .unwrap()
+});
+
+/// Runs a poll, recording how long it took and whether it failed
+pub async fn observe_poll(
+    poll: &str,
+    run: impl Future<Output = Result<(), anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let started_at = Instant::now();
+    let result = run.await;
+
+    POLL_DURATION
+        .with_label_values(&[poll])
+        .observe(started_at.elapsed().as_secs_f64());
+    let outcome = if result.is_ok() { "success" } else { "failure" };
+    POLL_RUNS.with_label_values(&[poll, outcome]).inc();
+
+    result
+}
+
+pub fn record_changes(poll: &str, changes: usize) {
+    POLL_CHANGES
+        .with_label_values(&[poll])
+        .inc_by(changes as u64);
+}
+
+pub fn record_graphql_error(query: &str) {
+    GRAPHQL_ERRORS.with_label_values(&[query]).inc();
+}
+
+pub fn set_circuit_open(poll: &str, open: bool) {
+    POLL_CIRCUIT_OPEN
+        .with_label_values(&[poll])
+        .set(open.into());
+}
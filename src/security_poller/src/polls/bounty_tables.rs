@@ -0,0 +1,246 @@
+use graphql_client::GraphQLQuery;
+use security_api::hackerone::{self, HackerOneClient};
+use security_api::models;
+use security_api::redis;
+
+use super::{PollConfiguration, TeamErrors};
+use crate::metrics;
+
+pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
+    debug!("running poll");
+
+    // bounty tables are only recorded for tracked programs
+    let mut errors = TeamErrors::default();
+    for team_handle in &config.team_handles {
+        let result = run_program_poll(config, team_handle).await;
+        errors.record(Some(team_handle), result);
+    }
+
+    errors.into_result()
+}
+
+async fn run_program_poll(
+    config: &PollConfiguration,
+    team_handle: &str,
+) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let last_data_key = models::redis_keys::BOUNTY_TABLES_POLL_LAST_DATA;
+    let previous_rows = get_saved_bounty_table(team_handle).await?;
+    let (currency, rows) = hackerone_get_bounty_table(team_handle, &config.hackerone).await?;
+
+    // First Run, a program without a bounty table is saved as an empty table
+    let Some(previous_rows) = previous_rows else {
+        redis::save_snapshot(last_data_key, Some(team_handle), &rows, &mut kv).await?;
+        return Ok(());
+    };
+
+    let changed = diff_rows(previous_rows, &rows);
+    debug!("bounty tables poll event: changed len: {}", changed.len());
+    metrics::record_changes("bounty_tables", changed.len());
+    if !changed.is_empty() {
+        let mut queue_item = models::BountyTableQueueItem {
+            id: None,
+            team_handle: String::from(team_handle),
+            currency,
+            diff: changed.clone(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+
+        queue_item.create_id();
+        redis::publish_to_stream(
+            &redis::key(models::redis_keys::BOUNTY_TABLES_QUEUE_STREAM),
+            &queue_item,
+            &mut kv,
+        )
+        .await?;
+    }
+
+    redis::save_snapshot(last_data_key, Some(team_handle), &rows, &mut kv).await?;
+    info!("ran poll for {}, {} changes", team_handle, changed.len());
+    Ok(())
+}
+
+/// `[old, new]` pairs for every added, removed or changed row
+fn diff_rows(
+    previous_rows: Vec<models::BountyTableRowData>,
+    rows: &[models::BountyTableRowData],
+) -> Vec<Vec<models::BountyTableRowData>> {
+    let mut changed = vec![];
+    for row in rows {
+        match previous_rows.iter().find(|old| old.id == row.id) {
+            None => changed.push(vec![models::BountyTableRowData::default(), row.clone()]),
+            Some(old) if old != row => changed.push(vec![old.clone(), row.clone()]),
+            _ => {}
+        }
+    }
+
+    for old in previous_rows {
+        if !rows.iter().any(|row| row.id == old.id) {
+            changed.push(vec![old, models::BountyTableRowData::default()]);
+        }
+    }
+
+    changed
+}
+
+/// `None` before the first run, a program without a bounty table has an empty saved table
+async fn get_saved_bounty_table(
+    team_handle: &str,
+) -> Result<Option<Vec<models::BountyTableRowData>>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let last_rows = redis::load_snapshot(
+        models::redis_keys::BOUNTY_TABLES_POLL_LAST_DATA,
+        Some(team_handle),
+        &mut kv,
+    )
+    .await?;
+    let Some(last_rows) = last_rows else {
+        return Ok(None);
+    };
+
+    let mut data = vec![];
+    for d in last_rows {
+        let deserialized = serde_json::from_str::<models::BountyTableRowData>(&d)?;
+        data.push(deserialized);
+    }
+
+    Ok(Some(data))
+}
+
+/// The team's currency and bounty table rows
+async fn hackerone_get_bounty_table(
+    handle: &str,
+    client: &HackerOneClient,
+) -> Result<(String, Vec<models::BountyTableRowData>), anyhow::Error> {
+    let variables = hackerone::team_bounty_table_query::Variables {
+        handle: String::from(handle),
+    };
+
+    let query = hackerone::TeamBountyTableQuery::build_query(variables);
+    let response = client
+        .http
+        .post("https://hackerone.com/graphql")
+        .json(&query)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::Error::msg("HackerOne API returned bad status code"));
+    }
+
+    let data =
+        response
+            .json::<graphql_client::Response<
+                <hackerone::TeamBountyTableQuery as GraphQLQuery>::ResponseData,
+            >>()
+            .await?;
+    trace!("{} {:?}", handle, data);
+    if let Some(errors) = data.errors {
+        if !errors.is_empty() {
+            metrics::record_graphql_error("TeamBountyTableQuery");
+            return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
+        }
+    }
+
+    let team = data.data.and_then(|data| data.team);
+    let Some(team) = team else {
+        return Err(anyhow::Error::msg(format!("cannot fetch team {}", handle)));
+    };
+
+    let currency = team.currency.unwrap_or(String::from("(unknown currency)"));
+    let rows = team
+        .bounty_table
+        .and_then(|bounty_table| bounty_table.bounty_table_rows)
+        .and_then(|rows| rows.nodes)
+        .unwrap_or_default();
+
+    let mut result = vec![];
+    for row in rows.into_iter().flatten() {
+        result.push(models::BountyTableRowData {
+            id: row.id,
+            asset_identifier: row.structured_scope.map(|scope| scope.asset_identifier),
+            low: row.low,
+            low_minimum: row.low_minimum,
+            medium: row.medium,
+            medium_minimum: row.medium_minimum,
+            high: row.high,
+            high_minimum: row.high_minimum,
+            critical: row.critical,
+            critical_minimum: row.critical_minimum,
+        });
+    }
+
+    Ok((currency, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: &str, high: i64, critical: i64) -> models::BountyTableRowData {
+        models::BountyTableRowData {
+            id: String::from(id),
+            asset_identifier: Some(format!("{}.example.com", id)),
+            high: Some(high),
+            critical: Some(critical),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_table_has_no_diff() {
+        let rows = vec![row("1", 1000, 5000), row("2", 500, 2000)];
+        assert!(diff_rows(rows.clone(), &rows).is_empty());
+        assert!(diff_rows(vec![], &[]).is_empty());
+    }
+
+    #[test]
+    fn diffs_added_and_removed_rows() {
+        let changed = diff_rows(vec![row("1", 1000, 5000)], &[row("2", 1000, 5000)]);
+        assert_eq!(
+            changed,
+            vec![
+                vec![models::BountyTableRowData::default(), row("2", 1000, 5000)],
+                vec![row("1", 1000, 5000), models::BountyTableRowData::default()],
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_changed_amounts() {
+        let previous = vec![row("1", 1000, 5000), row("2", 500, 2000)];
+        let mut unpaid = row("2", 500, 2000);
+        unpaid.critical = None;
+
+        let changed = diff_rows(previous, &[row("1", 1500, 5000), unpaid.clone()]);
+        assert_eq!(
+            changed,
+            vec![
+                vec![row("1", 1000, 5000), row("1", 1500, 5000)],
+                vec![row("2", 500, 2000), unpaid],
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_against_empty_table() {
+        // a program that had no bounty table on the previous run
+        let changed = diff_rows(vec![], &[row("1", 1000, 5000)]);
+        assert_eq!(
+            changed,
+            vec![vec![
+                models::BountyTableRowData::default(),
+                row("1", 1000, 5000)
+            ]]
+        );
+
+        let changed = diff_rows(vec![row("1", 1000, 5000)], &[]);
+        assert_eq!(
+            changed,
+            vec![vec![
+                row("1", 1000, 5000),
+                models::BountyTableRowData::default()
+            ]]
+        );
+    }
+}
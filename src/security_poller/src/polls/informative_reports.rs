@@ -1,22 +1,37 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use graphql_client::GraphQLQuery;
 use security_api::{
     hackerone::{self, HackerOneClient},
     models::{self, UserThanksData},
-    redis::{self, redis::AsyncCommands},
+    redis,
 };
 
 use crate::polls::reputation;
 
-use super::PollConfiguration;
+use super::{PollConfiguration, TeamErrors, WATCHLIST_SCOPE};
+use crate::metrics;
 
 pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     debug!("running poll");
+    let mut errors = TeamErrors::default();
+    for team_handle in config.team_scopes() {
+        let result = run_program_poll(config, team_handle).await;
+        errors.record(team_handle, result);
+    }
+
+    errors.into_result()
+}
 
+async fn run_program_poll(
+    config: &PollConfiguration,
+    team_handle: Option<&str>,
+) -> Result<(), anyhow::Error> {
     let mut kv = redis::get_connection().get().await?;
-    let last_saved_thanks_data = get_saved_thanks_data().await?;
-    let leaderboard = reputation::get_saved_reputation_data().await?;
+    let last_data_key =
+        redis::team_key(models::redis_keys::USER_THANKS_DATA_POLL_LAST_DATA, team_handle);
+    let last_saved_thanks_data = get_saved_thanks_data(&last_data_key).await?;
+    let leaderboard = reputation::get_saved_reputation_data(team_handle).await?;
     if leaderboard.is_none() {
         return Ok(());
     }
@@ -25,12 +40,28 @@ pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     let last_saved_thanks_data = last_saved_thanks_data.unwrap_or_default();
     let mut thanks_data = vec![];
 
-    // Fetch new Thanks data
-    let hackerone_program = config.team_handle.as_ref().map(|x| x.as_str());
+    // Fetch new Thanks data, watched researchers are followed into every program they're in
+    let watching_other_programs = team_handle == Some(WATCHLIST_SCOPE);
+    let program_filter = team_handle.filter(|_| !watching_other_programs);
+    let mut fetched_users = HashSet::new();
     for user in leaderboard {
+        if !config.is_watched(&user.user_name, &user.user_id) {
+            continue;
+        }
+
+        // the watchlist leaderboard has an entry per program, the thanks of every program
+        // are fetched at once
+        if !fetched_users.insert(user.user_id.clone()) {
+            continue;
+        }
+
         let username = &user.user_name;
         let mut user_thanks =
-            hackerone_get_user_thanks_data(&username, &config.hackerone, hackerone_program).await?;
+            hackerone_get_user_thanks_data(username, &config.hackerone, program_filter).await?;
+        if watching_other_programs {
+            user_thanks.retain(|thanks| !config.is_tracked_program(&thanks.team_handle));
+        }
+
         thanks_data.append(&mut user_thanks);
     }
 
@@ -58,6 +89,8 @@ pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
         }
     }
 
+    metrics::record_changes("informative_reports", changes.len());
+
     // Group
     let mut changes_grouped = HashMap::new();
     for change in changes {
@@ -76,35 +109,37 @@ pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     let changes = changes_grouped.len();
     if !changes_grouped.is_empty() {
         for (team_handle, changes) in changes_grouped {
-            let queue_item = models::InvalidReportsQueueItem {
+            let mut queue_item = models::InvalidReportsQueueItem {
+                id: None,
                 changes,
                 team_handle,
             };
-    
-            let queue_item_encoded = serde_json::to_string(&queue_item)?;
-            kv.publish::<&str, std::string::String, i32>(
-                models::redis_keys::USER_INFORMATIVE_REPORTS_QUEUE_PUBSUB,
-                queue_item_encoded,
+
+            queue_item.create_id();
+            redis::publish_to_stream(
+                &redis::key(models::redis_keys::USER_INFORMATIVE_REPORTS_QUEUE_STREAM),
+                &queue_item,
+                &mut kv,
             ).await?;
         }
     }
 
     // Save new data
     redis::save_vec_to_set(
-        models::redis_keys::USER_THANKS_DATA_POLL_LAST_DATA,
+        &last_data_key,
         thanks_data,
         true,
         &mut kv,
     ).await?;
-    info!("ran poll, {} changes", changes);
+    info!("ran poll for {}, {} changes", team_handle.unwrap_or("all programs"), changes);
     Ok(())
 }
 
-async fn get_saved_thanks_data() -> Result<Option<Vec<models::UserThanksData>>, anyhow::Error> {
+async fn get_saved_thanks_data(
+    key: &str,
+) -> Result<Option<Vec<models::UserThanksData>>, anyhow::Error> {
     let mut kv = redis::get_connection().get().await?;
-    let last_thanks_data =
-        redis::load_set_to_vec(models::redis_keys::USER_THANKS_DATA_POLL_LAST_DATA, &mut kv)
-            .await?;
+    let last_thanks_data = redis::load_set_to_vec(key, &mut kv).await?;
 
     let mut data = vec![];
     if last_thanks_data.is_empty() {
@@ -119,7 +154,7 @@ async fn get_saved_thanks_data() -> Result<Option<Vec<models::UserThanksData>>,
     Ok(Some(data))
 }
 
-async fn hackerone_get_user_thanks_data(
+pub async fn hackerone_get_user_thanks_data(
     username: &str,
     client: &HackerOneClient,
     hackerone_program: Option<&str>,
@@ -147,40 +182,47 @@ async fn hackerone_get_user_thanks_data(
     trace!("{} {:?}", username, data);
     if let Some(errors) = data.errors {
         if !errors.is_empty() {
+            metrics::record_graphql_error("UserProfileThanks");
             return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
         }
     }
 
-    let user = data.data.as_ref().unwrap().user.as_ref().unwrap();
-    let thanks_items = user.thanks_items.as_ref().unwrap().edges.as_ref().unwrap();
-    for program_thanks_data in thanks_items {
-        if let Some(program_thanks_data) = program_thanks_data {
-            let thanks_item = &program_thanks_data.node.as_ref().unwrap().thanks_item;
-            if let Some(team) = &thanks_item.team {
-                if let Some(hackerone_program) = &hackerone_program {
-                    if team.handle != *hackerone_program {
-                        continue;
-                    }
-                }
+    // renamed and deleted researchers are missing
+    let Some(user) = data.data.as_ref().and_then(|data| data.user.as_ref()) else {
+        return Err(anyhow::Error::msg(format!("researcher {} not found", username)));
+    };
 
-                let report_count = thanks_item.report_count.unwrap_or(0);
-                let total_report_count = thanks_item.total_report_count.unwrap_or(0);
-                let reputation = thanks_item.reputation.unwrap_or_default();
-
-                let user_thanks_data = UserThanksData {
-                    user_id: user.id.clone(),
-                    user_name: user.username.clone(),
-                    team_handle: team.handle.clone(),
-                    resolved_report_count: report_count,
-                    invalid_report_count: total_report_count - report_count,
-                    total_report_count,
-                    reputation,
-                };
+    let thanks_items = user.thanks_items.as_ref().and_then(|items| items.edges.as_ref());
+    for program_thanks_data in thanks_items.into_iter().flatten().flatten() {
+        let Some(node) = &program_thanks_data.node else {
+            continue;
+        };
 
-                thanks.push(user_thanks_data);
+        let thanks_item = &node.thanks_item;
+        if let Some(team) = &thanks_item.team {
+            if let Some(hackerone_program) = &hackerone_program {
+                if team.handle != *hackerone_program {
+                    continue;
+                }
             }
+
+            let report_count = thanks_item.report_count.unwrap_or(0);
+            let total_report_count = thanks_item.total_report_count.unwrap_or(0);
+            let reputation = thanks_item.reputation.unwrap_or_default();
+
+            let user_thanks_data = UserThanksData {
+                user_id: user.id.clone(),
+                user_name: user.username.clone(),
+                team_handle: team.handle.clone(),
+                resolved_report_count: report_count,
+                invalid_report_count: total_report_count - report_count,
+                total_report_count,
+                reputation,
+            };
+
+            thanks.push(user_thanks_data);
         }
     }
 
-    return Ok(thanks);
+    Ok(thanks)
 }
@@ -1,11 +1,363 @@
+use std::future::Future;
+use std::time::Duration;
+
 use security_api::hackerone::HackerOneClient;
+use security_api::health;
+use security_api::models::{self, ReportData};
+use security_api::redis::{self, redis::AsyncCommands};
+
+use crate::metrics;
+pub mod bounty_tables;
+pub mod policy;
 pub mod programs;
 pub mod reports;
 pub mod reputation;
+pub mod structured_scopes;
 pub mod informative_reports;
 
+/// Polls are unhealthy once they haven't succeeded for three intervals, but never sooner than
+/// this so short intervals can ride out a slow HackerOne
+static MIN_STALE_AFTER: Duration = Duration::from_secs(60 * 15);
+
+/// Registers the heartbeat of a poll that runs every `interval`
+pub async fn register(poll: &str, interval: Duration) {
+    health::register(poll, interval.saturating_mul(3).max(MIN_STALE_AFTER)).await;
+}
+
+/// Runs a poll, recording its outcome for the metrics and health endpoints
+pub async fn run(
+    poll: &str,
+    run: impl Future<Output = Result<(), anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let result = metrics::observe_poll(poll, run).await;
+    if result.is_ok() {
+        health::beat(poll).await;
+        if let Err(err) = save_last_success_time(poll).await {
+            error!("failed to save last success time of {}: {}", poll, err);
+        }
+    }
+
+    result
+}
+
+async fn save_last_success_time(poll: &str) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let key = redis::key(models::redis_keys::POLL_LAST_SUCCESS_TIME);
+    let now = chrono::Utc::now().timestamp_millis();
+
+    kv.hset::<_, _, _, ()>(&key, poll, now).await?;
+    Ok(())
+}
+
+/// Errors of the teams a poll failed for, so one failing team doesn't stop the others from
+/// being polled
+#[derive(Debug, Default)]
+pub struct TeamErrors(Vec<String>);
+
+impl TeamErrors {
+    pub fn record(&mut self, team_handle: Option<&str>, result: Result<(), anyhow::Error>) {
+        if let Err(err) = result {
+            let team_handle = team_handle.unwrap_or("all programs");
+            error!("poll failed for {}: {:?}", team_handle, err);
+            self.0.push(format!("{}: {}", team_handle, err));
+        }
+    }
+
+    /// Fails with the errors of every failed team once all of them were polled
+    pub fn into_result(self) -> Result<(), anyhow::Error> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        Err(anyhow::Error::msg(format!(
+            "poll failed for {} teams: {}",
+            self.0.len(),
+            self.0.join("; ")
+        )))
+    }
+}
+
+/// Scope for the programs watched researchers appear in outside of the tracked programs
+pub const WATCHLIST_SCOPE: &str = "@watchlist";
+
 #[derive(Clone)]
 pub struct PollConfiguration {
     pub hackerone: HackerOneClient,
-    pub team_handle: Option<String>,
+    pub team_handles: Vec<String>,
+    /// Usernames or user ids of the researchers changes are reported for, everyone if empty
+    pub watchlist: Vec<String>,
+    /// Also poll every program a watched researcher appears in
+    pub watch_all_programs: bool,
+    /// Most reports fetched from hacktivity per program and poll
+    pub max_reports_per_poll: usize,
+    pub report_filter: ReportFilter,
+}
+
+/// Database ids are numeric, usernames can't be
+fn is_user_id(watched: &str) -> bool {
+    watched.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Severities in increasing order, as HackerOne rates them
+const SEVERITIES: &[&str] = &["none", "low", "medium", "high", "critical"];
+
+/// Which disclosed reports are announced, every report is still tracked
+#[derive(Clone, Debug, Default)]
+pub struct ReportFilter {
+    /// Reports without a rating, or rated a severity not in [`SEVERITIES`] such as `unknown`,
+    /// don't match
+    pub min_severity: Option<String>,
+    /// Reports with a hidden bounty don't match
+    pub min_bounty: Option<f64>,
+    /// Matched case-insensitively against the title, summary and weakness, any of them
+    /// has to match
+    pub keywords: Vec<String>,
+    pub collaboration_only: bool,
+}
+
+impl ReportFilter {
+    pub fn matches(&self, report: &ReportData) -> bool {
+        if self.collaboration_only && !report.collaboration {
+            return false;
+        }
+
+        if let Some(min_bounty) = self.min_bounty {
+            if report.awarded_amount < min_bounty {
+                return false;
+            }
+        }
+
+        if let Some(min_severity) = &self.min_severity {
+            let rank = |severity: &str| SEVERITIES.iter().position(|s| *s == severity);
+            let severity = report.severity.as_deref().and_then(rank);
+            if severity.is_none() || severity < rank(min_severity) {
+                return false;
+            }
+        }
+
+        if !self.keywords.is_empty() {
+            let text = [&report.title, &report.summary, &report.cwe]
+                .iter()
+                .filter_map(|text| text.as_deref())
+                .collect::<Vec<&str>>()
+                .join("\n")
+                .to_lowercase();
+
+            return self
+                .keywords
+                .iter()
+                .any(|keyword| text.contains(&keyword.to_lowercase()));
+        }
+
+        true
+    }
+}
+
+impl PollConfiguration {
+    pub fn is_tracking_all_programs(&self) -> bool {
+        self.team_handles.is_empty()
+    }
+
+    /// Watched researchers are already covered in every program when tracking all programs
+    pub fn is_watching_other_programs(&self) -> bool {
+        self.watch_all_programs && !self.watchlist.is_empty() && !self.is_tracking_all_programs()
+    }
+
+    pub fn is_tracked_program(&self, handle: &str) -> bool {
+        self.team_handles
+            .iter()
+            .any(|team_handle| team_handle.eq_ignore_ascii_case(handle))
+    }
+
+    /// Watchlist entries are matched against usernames (case-insensitively) and database ids
+    pub fn is_watched(&self, user_name: &str, user_id: &str) -> bool {
+        self.watchlist.is_empty()
+            || self
+                .watchlist
+                .iter()
+                .any(|watched| watched.eq_ignore_ascii_case(user_name) || watched == user_id)
+    }
+
+    /// Watchlist entries that are usernames, the thanks of a researcher can only be looked
+    /// up by username
+    pub fn watched_usernames(&self) -> impl Iterator<Item = &str> {
+        self.watchlist
+            .iter()
+            .map(String::as_str)
+            .filter(|watched| !is_user_id(watched))
+    }
+
+    /// Every program polled separately, or a single `None` scope when tracking all programs.
+    /// [`WATCHLIST_SCOPE`] is added when watched researchers are followed into other programs.
+    pub fn team_scopes(&self) -> Vec<Option<&str>> {
+        if self.is_tracking_all_programs() {
+            return vec![None];
+        }
+
+        let mut scopes: Vec<Option<&str>> =
+            self.team_handles.iter().map(|h| Some(h.as_str())).collect();
+        if self.is_watching_other_programs() {
+            scopes.push(Some(WATCHLIST_SCOPE));
+        }
+
+        scopes
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn config(watchlist: &[&str]) -> PollConfiguration {
+        PollConfiguration {
+            hackerone: HackerOneClient::new(String::from("csrf"), String::new()),
+            team_handles: vec![String::from("security")],
+            watchlist: watchlist.iter().map(|watched| String::from(*watched)).collect(),
+            watch_all_programs: false,
+            max_reports_per_poll: 100,
+            report_filter: ReportFilter::default(),
+        }
+    }
+
+    #[test]
+    fn collects_team_errors() {
+        let mut errors = TeamErrors::default();
+        errors.record(Some("security"), Ok(()));
+        assert!(errors.0.is_empty());
+
+        errors.record(Some("security"), Err(anyhow::Error::msg("bad status")));
+        errors.record(None, Err(anyhow::Error::msg("timeout")));
+        let err = errors.into_result().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "poll failed for 2 teams: security: bad status; all programs: timeout"
+        );
+        assert!(TeamErrors::default().into_result().is_ok());
+    }
+
+    #[test]
+    fn watches_usernames_and_ids() {
+        let everyone = config(&[]);
+        assert!(everyone.is_watched("hackermon", "1337"));
+
+        let config = config(&["HackerMon", "42"]);
+        assert!(config.is_watched("hackermon", "1337"));
+        assert!(config.is_watched("renamed", "42"));
+        assert!(!config.is_watched("someone", "1337"));
+        assert!(!config.is_watched("", ""));
+
+        // ids can't be looked up by username
+        assert_eq!(config.watched_usernames().collect::<Vec<_>>(), vec!["HackerMon"]);
+    }
+
+    fn report(severity: Option<&str>, awarded_amount: f64) -> ReportData {
+        ReportData {
+            title: Some(String::from("Stored XSS in the inbox")),
+            summary: Some(String::from("An attacker can run JavaScript in a victim's browser")),
+            cwe: Some(String::from("Cross-site Scripting (XSS) - Stored")),
+            severity: severity.map(String::from),
+            awarded_amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn filters_reports_by_severity() {
+        let filter = ReportFilter {
+            min_severity: Some(String::from("medium")),
+            ..Default::default()
+        };
+
+        assert!(filter.matches(&report(Some("critical"), 0.0)));
+        assert!(filter.matches(&report(Some("medium"), 0.0)));
+        assert!(!filter.matches(&report(Some("low"), 0.0)));
+        assert!(!filter.matches(&report(Some("none"), 0.0)));
+        assert!(!filter.matches(&report(Some("unknown"), 0.0)));
+        assert!(!filter.matches(&report(None, 0.0)));
+
+        let filter = ReportFilter {
+            min_severity: Some(String::from("none")),
+            ..Default::default()
+        };
+        assert!(filter.matches(&report(Some("none"), 0.0)));
+        assert!(!filter.matches(&report(Some("unknown"), 0.0)));
+    }
+
+    #[test]
+    fn filters_reports_by_bounty() {
+        let filter = ReportFilter {
+            min_bounty: Some(500.0),
+            ..Default::default()
+        };
+
+        assert!(filter.matches(&report(Some("high"), 500.0)));
+        assert!(!filter.matches(&report(Some("high"), 499.99)));
+        // hidden bounties are saved as -1
+        assert!(!filter.matches(&report(Some("high"), -1.0)));
+
+        let filter = ReportFilter {
+            min_bounty: Some(0.0),
+            ..Default::default()
+        };
+        assert!(filter.matches(&report(None, 0.0)));
+        assert!(!filter.matches(&report(None, -1.0)));
+    }
+
+    #[test]
+    fn filters_reports_by_keywords() {
+        let filter = ReportFilter {
+            keywords: vec![String::from("ssrf"), String::from("JAVASCRIPT")],
+            ..Default::default()
+        };
+        assert!(filter.matches(&report(None, -1.0)));
+
+        let filter = ReportFilter {
+            keywords: vec![String::from("ssrf")],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&report(None, -1.0)));
+
+        let filter = ReportFilter {
+            keywords: vec![String::from("stored")],
+            ..Default::default()
+        };
+        let mut report = report(None, -1.0);
+        report.title = None;
+        report.summary = None;
+        assert!(filter.matches(&report));
+    }
+
+    #[test]
+    fn filters_collaborations() {
+        let filter = ReportFilter {
+            collaboration_only: true,
+            ..Default::default()
+        };
+
+        let mut report = report(Some("high"), 100.0);
+        assert!(!filter.matches(&report));
+        report.collaboration = true;
+        assert!(filter.matches(&report));
+        assert!(ReportFilter::default().matches(&report));
+    }
+
+    #[test]
+    fn every_criterion_has_to_match() {
+        let filter = ReportFilter {
+            min_severity: Some(String::from("high")),
+            min_bounty: Some(1000.0),
+            keywords: vec![String::from("xss")],
+            collaboration_only: false,
+        };
+
+        assert!(filter.matches(&report(Some("high"), 1000.0)));
+        assert!(!filter.matches(&report(Some("medium"), 1000.0)));
+        assert!(!filter.matches(&report(Some("high"), 999.0)));
+
+        let mut report = report(Some("high"), 1000.0);
+        report.cwe = None;
+        report.title = Some(String::from("SQL injection"));
+        report.summary = None;
+        assert!(!filter.matches(&report));
+    }
 }
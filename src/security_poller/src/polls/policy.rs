@@ -0,0 +1,182 @@
+use graphql_client::GraphQLQuery;
+use security_api::hackerone::{self, HackerOneClient};
+use security_api::models;
+use security_api::redis::{self, redis::AsyncCommands};
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use super::{PollConfiguration, TeamErrors};
+use crate::metrics;
+
+/// Lines of unchanged policy text kept around every change in the diff
+static DIFF_CONTEXT_LINES: usize = 2;
+
+pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
+    debug!("running poll");
+
+    // policies are only tracked for tracked programs
+    let mut errors = TeamErrors::default();
+    for team_handle in &config.team_handles {
+        let result = run_program_poll(config, team_handle).await;
+        errors.record(Some(team_handle), result);
+    }
+
+    errors.into_result()
+}
+
+async fn run_program_poll(
+    config: &PollConfiguration,
+    team_handle: &str,
+) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let last_data_key =
+        redis::team_key(models::redis_keys::POLICY_POLL_LAST_DATA, Some(team_handle));
+    let last_policy: Vec<Option<String>> = kv.hget(&last_data_key, &["hash", "text"]).await?;
+
+    // diffing a missing policy against the saved one would announce it as deleted
+    let Some(policy) = hackerone_get_policy(team_handle, &config.hackerone).await? else {
+        warn!("{} returned no policy, skipping", team_handle);
+        return Ok(());
+    };
+
+    let hash = hash_policy(&policy);
+
+    let (last_hash, last_text) = match last_policy.as_slice() {
+        [Some(last_hash), Some(last_text)] => (last_hash, last_text),
+        _ => {
+            // first run
+            save_policy(&last_data_key, &hash, &policy).await?;
+            return Ok(());
+        }
+    };
+
+    let Some(diff) = diff_policy(last_hash, last_text, &hash, &policy) else {
+        debug!("policy of {} is unchanged", team_handle);
+        return Ok(());
+    };
+
+    metrics::record_changes("policy", 1);
+    let mut queue_item = models::PolicyQueueItem {
+        id: None,
+        team_handle: String::from(team_handle),
+        old_hash: last_hash.clone(),
+        new_hash: hash.clone(),
+        diff,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+
+    queue_item.create_id();
+    redis::publish_to_stream(
+        &redis::key(models::redis_keys::POLICY_QUEUE_STREAM),
+        &queue_item,
+        &mut kv,
+    )
+    .await?;
+
+    save_policy(&last_data_key, &hash, &policy).await?;
+    info!("ran poll for {}, policy changed", team_handle);
+    Ok(())
+}
+
+fn hash_policy(policy: &str) -> String {
+    hex::encode(Sha256::digest(policy.as_bytes()))
+}
+
+/// Unified diff from the saved policy to the current one, `None` if it's unchanged
+fn diff_policy(last_hash: &str, last_text: &str, hash: &str, policy: &str) -> Option<String> {
+    if last_hash == hash {
+        return None;
+    }
+
+    let diff = TextDiff::from_lines(last_text, policy)
+        .unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .to_string();
+    Some(diff)
+}
+
+async fn save_policy(key: &str, hash: &str, policy: &str) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    kv.hset_multiple::<_, _, _, ()>(key, &[("hash", hash), ("text", policy)])
+        .await?;
+    Ok(())
+}
+
+/// The team's policy, `None` if HackerOne returned none
+async fn hackerone_get_policy(
+    handle: &str,
+    client: &HackerOneClient,
+) -> Result<Option<String>, anyhow::Error> {
+    let variables = hackerone::team_policy_query::Variables {
+        handle: String::from(handle),
+    };
+
+    let query = hackerone::TeamPolicyQuery::build_query(variables);
+    let response = client
+        .http
+        .post("https://hackerone.com/graphql")
+        .json(&query)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::Error::msg("HackerOne API returned bad status code"));
+    }
+
+    let data = response.json::<graphql_client::Response<<hackerone::TeamPolicyQuery as GraphQLQuery>::ResponseData>>().await?;
+    trace!("{} {:?}", handle, data);
+    if let Some(errors) = data.errors {
+        if !errors.is_empty() {
+            metrics::record_graphql_error("TeamPolicyQuery");
+            return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
+        }
+    }
+
+    let team = data.data.and_then(|data| data.team);
+    let Some(team) = team else {
+        return Err(anyhow::Error::msg(format!("cannot fetch team {}", handle)));
+    };
+
+    Ok(team.policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(last_text: &str, policy: &str) -> Option<String> {
+        diff_policy(
+            &hash_policy(last_text),
+            last_text,
+            &hash_policy(policy),
+            policy,
+        )
+    }
+
+    #[test]
+    fn unchanged_policy_has_no_diff() {
+        assert_eq!(diff("# Policy\nBe nice\n", "# Policy\nBe nice\n"), None);
+        assert_eq!(diff("", ""), None);
+    }
+
+    #[test]
+    fn diffs_changed_lines_with_context() {
+        let last_text = "# Policy\na\nb\nc\nd\ne\nf\n";
+        let policy = "# Policy\na\nb\nc\nd\ne\nchanged\n";
+
+        let diff = diff(last_text, policy).unwrap();
+        assert!(diff.contains("-f\n"));
+        assert!(diff.contains("+changed\n"));
+        assert!(diff.contains(" d\n e\n"));
+        assert!(!diff.contains(" c\n"));
+    }
+
+    #[test]
+    fn diffs_against_empty_policy() {
+        let added = diff("", "# Policy\n").unwrap();
+        assert!(added.contains("+# Policy\n"));
+
+        let removed = diff("# Policy\n", "").unwrap();
+        assert!(removed.contains("-# Policy\n"));
+    }
+}
@@ -10,29 +10,88 @@ use security_api::{
 };
 
 use super::PollConfiguration;
+use crate::metrics;
 
 pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     debug!("running poll");
 
     let mut kv = redis::get_connection().get().await?;
+    let last_data_key = redis::key(models::redis_keys::PROGRAMS_POLL_LAST_DATA);
+    let previous_programs = get_saved_programs_data(&last_data_key).await?;
     let programs = get_all_hackerone_programs(&config.hackerone, None).await?;
 
     info!("got {} programs", programs.len());
     trace!("{:#?}", programs);
 
+    if programs.is_empty() {
+        warn!("discovery returned no programs, keeping the previous list");
+        return Ok(());
+    }
+
+    // nothing to diff against on the first run
+    if let Some(previous_programs) = previous_programs {
+        let launched: Vec<models::ProgramData> = programs
+            .iter()
+            .filter(|p| !previous_programs.iter().any(|old| old.handle == p.handle))
+            .cloned()
+            .collect();
+        let retired: Vec<models::ProgramData> = previous_programs
+            .into_iter()
+            .filter(|old| !programs.iter().any(|p| p.handle == old.handle))
+            .collect();
+
+        debug!("programs poll event: launched {}, retired {}", launched.len(), retired.len());
+        metrics::record_changes("programs", launched.len() + retired.len());
+        if !launched.is_empty() || !retired.is_empty() {
+            let mut queue_item = models::ProgramsQueueItem {
+                id: None,
+                launched,
+                retired,
+                created_at: chrono::Utc::now().naive_utc(),
+            };
+
+            queue_item.create_id();
+            redis::publish_to_stream(
+                &redis::key(models::redis_keys::PROGRAMS_QUEUE_STREAM),
+                &queue_item,
+                &mut kv,
+            ).await?;
+        }
+    }
+
+    let handles: Vec<String> = programs.iter().map(|p| p.handle.clone()).collect();
     save_vec_to_set(
-        models::redis_keys::PROGRAMS,
-        programs,
-        false,
+        &redis::key(models::redis_keys::PROGRAMS),
+        handles,
+        true,
         &mut kv,
     ).await?;
+    save_vec_to_set(&last_data_key, programs, true, &mut kv).await?;
     Ok(())
 }
 
+async fn get_saved_programs_data(
+    key: &str,
+) -> Result<Option<Vec<models::ProgramData>>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let last_programs_data = redis::load_set_to_vec(key, &mut kv).await?;
+    if last_programs_data.is_empty() {
+        return Ok(None);
+    }
+
+    let mut data = vec![];
+    for d in last_programs_data {
+        let deserialized = serde_json::from_str::<models::ProgramData>(&d)?;
+        data.push(deserialized);
+    }
+
+    Ok(Some(data))
+}
+
 async fn get_all_hackerone_programs(
     client: &HackerOneClient,
     after: Option<usize>,
-) -> Result<Vec<String>, anyhow::Error> {
+) -> Result<Vec<models::ProgramData>, anyhow::Error> {
     let after = after.unwrap_or(0);
     let variables = hackerone::discovery_query::Variables {
         size: Some(100),
@@ -57,6 +116,7 @@ async fn get_all_hackerone_programs(
     let data = response.json::<graphql_client::Response<<hackerone::DiscoveryQuery as GraphQLQuery>::ResponseData>>().await?;
     if let Some(errors) = data.errors {
         if !errors.is_empty() {
+            metrics::record_graphql_error("DiscoveryQuery");
             return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
         }
     }
@@ -80,7 +140,12 @@ async fn get_all_hackerone_programs(
 
         let program = item.as_ref().unwrap();
         if let DiscoveryQueryOpportunitiesSearchNodes::OpportunityDocument(program) = program {
-            program_names.push(program.handle.clone());
+            program_names.push(models::ProgramData {
+                handle: program.handle.clone(),
+                name: program.name.clone(),
+                profile_picture_url: program.profile_picture.clone(),
+                offers_bounties: program.offers_bounties.unwrap_or(false),
+            });
         };
     }
 
@@ -1,34 +1,52 @@
-use super::PollConfiguration;
-extern crate cronjob;
+use super::{PollConfiguration, TeamErrors, WATCHLIST_SCOPE};
+use crate::metrics;
 use chrono;
 use graphql_client::GraphQLQuery;
 use security_api::hackerone::{self as hackerone, HackerOneClient};
 use security_api::models::{self as models, ReportData};
 use security_api::redis::{self, redis::AsyncCommands};
 
+static REPORTS_PAGE_SIZE: i64 = 25;
+static REPORT_RETENTION_DAYS: i64 = 90;
+static MAX_SAVED_REPORTS: usize = 5_000;
+
 pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     debug!("running poll");
+    let mut errors = TeamErrors::default();
+    for team_handle in config.team_scopes() {
+        let result = run_program_poll(config, team_handle).await;
+        errors.record(team_handle, result);
+    }
+
+    errors.into_result()
+}
+
+async fn run_program_poll(
+    config: &PollConfiguration,
+    team_handle: Option<&str>,
+) -> Result<(), anyhow::Error> {
     let mut kv = redis::get_connection().get().await?;
-    let last_run_time: Option<String> = kv.get(models::redis_keys::REPORTS_POLL_LAST_RUN_TIME).await?;
+    let keys = ReportStateKeys::for_team(team_handle);
+    let last_run_time_key =
+        redis::team_key(models::redis_keys::REPORTS_POLL_LAST_RUN_TIME, team_handle);
+    let last_run_time: Option<String> = kv.get(&last_run_time_key).await?;
 
-    let last_report_data = get_old_reports_data().await?;
+    let last_report_data = get_old_reports_data(&keys).await?;
     let mut team_name = None;
 
-    if let Some(team_handle) = &config.team_handle {
+    // watched researchers are searched for in every program
+    if let Some(team_handle) = team_handle.filter(|handle| *handle != WATCHLIST_SCOPE) {
         let _team_name = hackerone_get_team_name(team_handle, &config.hackerone).await?;
         let _ = team_name.insert(_team_name);
     }
 
-    let report_data = hackerone_get_reports_data(team_name, &config.hackerone).await?;
+    // the first run only saves the latest page as a starting point
+    let seen_reports = last_run_time.as_ref().and(last_report_data.as_deref());
+    let report_data = hackerone_get_new_reports_data(team_name.as_deref(), seen_reports, config).await?;
     if last_run_time.is_none() || last_report_data.is_none() {
         // first run
-        redis::save_vec_to_set(
-            models::redis_keys::REPORTS_POLL_LAST_DATA,
-            report_data,
-            false,
-            &mut kv,
-        ).await?;
-        set_last_run_time_now().await?;
+        save_reports_data(&keys, &report_data).await?;
+        set_last_run_time_now(&last_run_time_key).await?;
         return Ok(());
     }
 
@@ -44,59 +62,76 @@ pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
             .find(|p| p.id.as_ref().unwrap_or(&"".into()) == &report_id);
 
         trace!("{:#?}", report);
-        if old_data.is_none() {
-            // new report
-            let empty = models::ReportData::default();
-            let diff: Vec<models::ReportData> = vec![empty, report];
-            changed.push(diff);
-        } else if !old_data.unwrap().disclosed && report.disclosed {
-            let diff: Vec<models::ReportData> = vec![old_data.unwrap().clone(), report.clone()];
-            changed.push(diff);
+        match old_data {
+            None if is_past_retention(&report, chrono::Utc::now()) => {
+                // an old report bumped back up after its state was pruned
+                debug!("report {} was pruned, not announcing it again", report_id);
+            }
+            None => {
+                // new report
+                let empty = models::ReportData::default();
+                let diff: Vec<models::ReportData> = vec![empty, report];
+                changed.push(diff);
+            }
+            Some(old_data) if !old_data.changes(&report).is_empty() => {
+                let diff: Vec<models::ReportData> = vec![old_data.clone(), report.clone()];
+                changed.push(diff);
+            }
+            _ => {}
         }
     }
 
+    changed.retain(|diff| {
+        let report = &diff[1];
+        let is_tracked_program = report
+            .team_handle
+            .as_deref()
+            .is_some_and(|handle| config.is_tracked_program(handle));
+
+        // reports in tracked programs are already reported by their own scope
+        let is_duplicate = team_handle == Some(WATCHLIST_SCOPE) && is_tracked_program;
+        !is_duplicate
+            && config.is_watched(&report.user_name, &report.user_id)
+            && config.report_filter.matches(report)
+    });
+
     debug!("reports poll event: changed len: {}", changed.len());
+    metrics::record_changes("reports", changed.len());
     if !changed.is_empty() {
         let mut queue_item = models::ReportsDataQueueItem {
             id: None,
-            team_handle: config.team_handle.clone(),
+            team_handle: team_handle.filter(|h| *h != WATCHLIST_SCOPE).map(String::from),
             diff: changed.clone(),
             created_at: chrono::Utc::now().naive_utc(),
         };
 
         queue_item.create_id();
-        let queue_item_encoded = serde_json::to_string(&queue_item).unwrap();
-        kv.publish::<&str, std::string::String, i32>(
-            models::redis_keys::REPORTS_QUEUE_PUBSUB,
-            queue_item_encoded,
+        redis::publish_to_stream(
+            &redis::key(models::redis_keys::REPORTS_QUEUE_STREAM),
+            &queue_item,
+            &mut kv,
         ).await?;
     }
 
-    if last_report_data.is_some() {
-        let last_report_data = last_report_data.unwrap();
+    if let Some(last_report_data) = last_report_data {
         if !last_report_data.is_empty() && report_data_cloned.is_empty() {
             return Ok(());
         }
     }
 
-    redis::save_vec_to_set(
-        models::redis_keys::REPORTS_POLL_LAST_DATA,
-        report_data_cloned,
-        false,
-        &mut kv,
-    ).await?;
-    set_last_run_time_now().await?;
+    save_reports_data(&keys, &report_data_cloned).await?;
+    set_last_run_time_now(&last_run_time_key).await?;
 
-    info!("ran poll, {} changes", changed.len());
+    info!("ran poll for {}, {} changes", team_handle.unwrap_or("all programs"), changed.len());
     Ok(())
 }
 
-async fn set_last_run_time_now() -> Result<(), anyhow::Error> {
+async fn set_last_run_time_now(key: &str) -> Result<(), anyhow::Error> {
     let mut kv = redis::get_connection().get().await?;
     let now = chrono::Utc::now();
     let ms = now.timestamp_millis();
 
-    kv.set::<_, _, ()>(models::redis_keys::REPORTS_POLL_LAST_RUN_TIME, ms).await?;
+    kv.set::<_, _, ()>(key, ms).await?;
     Ok(())
 }
 
@@ -112,6 +147,7 @@ async fn hackerone_get_team_name(handle: &str, client: &HackerOneClient) -> Resu
     let data = response.json::<graphql_client::Response<<hackerone::TeamNameHacktivityQuery as GraphQLQuery>::ResponseData>>().await?;
     if let Some(errors) = data.errors {
         if !errors.is_empty() {
+            metrics::record_graphql_error("TeamNameHacktivityQuery");
             return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
         }
     }
@@ -120,16 +156,103 @@ async fn hackerone_get_team_name(handle: &str, client: &HackerOneClient) -> Resu
     Ok(team.name)
 }
 
+/// Pages through hacktivity until reaching a report that was seen before and hasn't changed
+/// since, so bursts of disclosures between polls aren't cut off after the first page
+async fn hackerone_get_new_reports_data(
+    team_name: Option<&str>,
+    seen_reports: Option<&[models::ReportData]>,
+    config: &PollConfiguration,
+) -> Result<Vec<models::ReportData>, anyhow::Error> {
+    let mut reports: Vec<models::ReportData> = vec![];
+    let mut from = 0;
+
+    loop {
+        let (page, total_count) =
+            hackerone_get_reports_data(team_name, from, &config.hackerone).await?;
+        from += REPORTS_PAGE_SIZE;
+
+        let Some(seen_reports) = seen_reports else {
+            return Ok(page);
+        };
+
+        let stop = add_page(
+            &mut reports,
+            page,
+            seen_reports,
+            from,
+            total_count,
+            config.max_reports_per_poll,
+        );
+        match stop {
+            Some(PagingStop::Capped) => {
+                warn!(
+                    "stopped paging {} after {} reports, some disclosures may be missed",
+                    team_name.unwrap_or("hacktivity"),
+                    reports.len()
+                );
+                break;
+            }
+            Some(_) => break,
+            None => {}
+        }
+    }
+
+    Ok(reports)
+}
+
+#[derive(Debug, PartialEq)]
+enum PagingStop {
+    /// The page had a report that was seen before and hasn't changed since
+    ReachedSeenReports,
+    LastPage,
+    /// Fetched `max_reports_per_poll` reports without reaching a seen one
+    Capped,
+}
+
+/// Adds a page of hacktivity to `reports` after `fetched` reports were requested, returns
+/// why paging should stop or `None` to fetch the next page
+fn add_page(
+    reports: &mut Vec<models::ReportData>,
+    page: Vec<models::ReportData>,
+    seen_reports: &[models::ReportData],
+    fetched: i64,
+    total_count: i64,
+    max_reports: usize,
+) -> Option<PagingStop> {
+    let reached_seen_reports = page.iter().any(|report| {
+        seen_reports
+            .iter()
+            .any(|old| report.id.is_some() && old.id == report.id && old.changes(report).is_empty())
+    });
+
+    for report in page {
+        // reports can shift between pages while paging
+        if !reports.iter().any(|r| r.id == report.id) {
+            reports.push(report);
+        }
+    }
+
+    if reached_seen_reports {
+        Some(PagingStop::ReachedSeenReports)
+    } else if fetched >= total_count {
+        Some(PagingStop::LastPage)
+    } else if reports.len() >= max_reports {
+        Some(PagingStop::Capped)
+    } else {
+        None
+    }
+}
+
 #[rustfmt::skip]
-async fn hackerone_get_reports_data(team_name: Option<String>, client: &HackerOneClient) -> Result<Vec<models::ReportData>, anyhow::Error> {
+async fn hackerone_get_reports_data(team_name: Option<&str>, from: i64, client: &HackerOneClient) -> Result<(Vec<models::ReportData>, i64), anyhow::Error> {
     let mut query_string = String::from("disclosed:true");
     if let Some(team_name) = team_name {
         query_string += &format!("&& team:(\"{}\")", team_name);
     }
 
     let variables = hackerone::complete_hacktivity_search_query::Variables {
-        from: Some(0),
-        size: Some(10),
+        from: Some(from),
+        size: Some(REPORTS_PAGE_SIZE),
         query_string,
         sort: hackerone::complete_hacktivity_search_query::SortInput {
             direction: Some(hackerone::complete_hacktivity_search_query::OrderDirection::DESC),
@@ -144,11 +267,14 @@ async fn hackerone_get_reports_data(team_name: Option<String>, client: &HackerOn
     let data = response.json::<graphql_client::Response<<hackerone::CompleteHacktivitySearchQuery as GraphQLQuery>::ResponseData>>().await?;
     if let Some(errors) = data.errors {
         if !errors.is_empty() {
+            metrics::record_graphql_error("CompleteHacktivitySearchQuery");
             return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
         }
     }
     
-    let reports = data.data.unwrap().search.unwrap().nodes.unwrap();
+    let search = data.data.unwrap().search.unwrap();
+    let total_count = search.total_count;
+    let reports = search.nodes.unwrap();
     for item in reports {
         if item.is_none() {
             continue
@@ -170,27 +296,33 @@ async fn hackerone_get_reports_data(team_name: Option<String>, client: &HackerOn
             let hackerone_report = hackerone_report.unwrap();
             report.id = Some(hackerone_report.id.clone());
             report.title = hackerone_report.title.clone();
+            report.team_handle = Some(team.handle.clone());
             report.currency = team.currency.clone().unwrap_or(String::from("(unknown currency)"));
             report.awarded_amount = _hackerone_report.total_awarded_amount.unwrap_or(-1) as f64;
             report.disclosed = true;
+            report.substate = Some(hackerone_report.substate.clone());
+            report.cve_ids = Some(_hackerone_report.cve_ids.clone().unwrap_or_default());
+            report.votes = _hackerone_report.votes;
+            report.cwe = _hackerone_report.cwe.clone();
+            report.submitted_at = _hackerone_report.submitted_at.clone();
+            report.disclosed_at = hackerone_report.disclosed_at.clone();
+            report.team_name = Some(team.name.clone());
+            report.team_profile_picture_url = Some(team.medium_profile_picture.clone());
             report.url = Some(format!("https://hackerone.com/reports/{}", _hackerone_report.id));
             report.collaboration = _hackerone_report.has_collaboration.unwrap_or(false);
-            report.summary = if hackerone_report.report_generated_content.is_some() {
-                let summary = hackerone_report.report_generated_content.as_ref().unwrap().hacktivity_summary.clone();
-                Some(summary.unwrap_or(String::from("This report does not have a summary")))
-            } else {
-                None
-            };
+            report.summary = hackerone_report.report_generated_content.as_ref().map(|content| {
+                let summary = content.hacktivity_summary.clone();
+                summary.unwrap_or(String::from("This report does not have a summary"))
+            });
 
-            report.severity = Some(if _hackerone_report.severity_rating.is_none() {
-                String::from("unknown")
-            } else {
-                _hackerone_report.severity_rating.unwrap().to_lowercase()
+            report.severity = Some(match _hackerone_report.severity_rating {
+                Some(severity_rating) => severity_rating.to_lowercase(),
+                None => String::from("unknown"),
             });
 
-            if _hackerone_report.reporter.is_some() {
-                report.user_name = _hackerone_report.reporter.as_ref().unwrap().username.clone();
-                report.user_id = _hackerone_report.reporter.unwrap().id;
+            if let Some(reporter) = _hackerone_report.reporter {
+                report.user_name = reporter.username;
+                report.user_id = reporter.database_id;
             } else {
                 report.user_name = "(unknown)".into();
                 report.user_id = "1".into();
@@ -200,21 +332,282 @@ async fn hackerone_get_reports_data(team_name: Option<String>, client: &HackerOn
         result.push(report);
     }
 
-    Ok(result)
+    Ok((result, total_count))
+}
+
+struct ReportStateKeys {
+    state: String,
+    seen: String,
+    legacy: String,
+}
+
+impl ReportStateKeys {
+    fn for_team(team_handle: Option<&str>) -> Self {
+        ReportStateKeys {
+            state: redis::team_key(models::redis_keys::REPORTS_POLL_STATE, team_handle),
+            seen: redis::team_key(models::redis_keys::REPORTS_POLL_SEEN, team_handle),
+            legacy: redis::team_key(models::redis_keys::REPORTS_POLL_LAST_DATA, team_handle),
+        }
+    }
+}
+
+/// Reports fetched again replace their saved version, reports that haven't been fetched
+/// for [`REPORT_RETENTION_DAYS`] or beyond the [`MAX_SAVED_REPORTS`] most recent are pruned
+async fn save_reports_data(keys: &ReportStateKeys, reports: &[models::ReportData]) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let mut state = vec![];
+    let mut seen = vec![];
+    for report in reports {
+        let Some(id) = &report.id else {
+            continue;
+        };
+
+        state.push((id.clone(), serde_json::to_string(report)?));
+        seen.push((now, id.clone()));
+    }
+
+    if !state.is_empty() {
+        kv.hset_multiple::<_, _, _, ()>(&keys.state, &state).await?;
+        kv.zadd_multiple::<_, _, _, ()>(&keys.seen, &seen).await?;
+    }
+
+    let seen: Vec<(String, f64)> = kv.zrange_withscores(&keys.seen, 0, -1).await?;
+    let pruned = pruned_reports(&seen, now, MAX_SAVED_REPORTS);
+
+    if !pruned.is_empty() {
+        debug!("pruning {} saved reports", pruned.len());
+        kv.hdel::<_, _, ()>(&keys.state, &pruned).await?;
+        kv.zrem::<_, _, ()>(&keys.seen, &pruned).await?;
+    }
+
+    Ok(())
+}
+
+/// Ids of the saved reports to prune, `seen` holds the ids with when they were last fetched
+/// (milliseconds), oldest first
+fn pruned_reports(seen: &[(String, f64)], now: i64, max_saved: usize) -> Vec<String> {
+    let retention_start = now - chrono::Duration::days(REPORT_RETENTION_DAYS).num_milliseconds();
+    let expired = seen
+        .iter()
+        .take_while(|(_, fetched_at)| *fetched_at <= retention_start as f64)
+        .count();
+    let excess = seen.len().saturating_sub(max_saved);
+
+    seen[..expired.max(excess)].iter().map(|(id, _)| id.clone()).collect()
+}
+
+/// Reports disclosed before the retention window may have been pruned already. Reports
+/// without a disclosure date can't be placed, they are treated as recent so a new report is
+/// announced rather than dropped.
+fn is_past_retention(report: &models::ReportData, now: chrono::DateTime<chrono::Utc>) -> bool {
+    let disclosed_at = match report.disclosed_at.as_deref() {
+        Some(date) => chrono::DateTime::parse_from_rfc3339(date),
+        None => return false,
+    };
+
+    match disclosed_at {
+        Ok(disclosed_at) => {
+            now.signed_duration_since(disclosed_at) > chrono::Duration::days(REPORT_RETENTION_DAYS)
+        }
+        Err(err) => {
+            warn!("report {:?} has an invalid disclosure date: {}", report.id, err);
+            false
+        }
+    }
 }
 
-async fn get_old_reports_data() -> Result<Option<Vec<models::ReportData>>, anyhow::Error> {
+async fn get_old_reports_data(keys: &ReportStateKeys) -> Result<Option<Vec<models::ReportData>>, anyhow::Error> {
     let mut kv = redis::get_connection().get().await?;
-    let last_reports_data = redis::load_set_to_vec(
-        models::redis_keys::REPORTS_POLL_LAST_DATA,
-        &mut kv,
-    ).await?;
+    migrate_legacy_reports_data(keys).await?;
 
+    let last_reports_data: Vec<String> = kv.hvals(&keys.state).await?;
     let mut data: Vec<models::ReportData> = vec![];
     for d in last_reports_data {
-        let deserialized = serde_json::from_str::<models::ReportData>(&d).unwrap();
+        let deserialized = serde_json::from_str::<models::ReportData>(&d)?;
         data.push(deserialized);
     }
 
     Ok(Some(data))
 }
+
+/// Moves reports saved by older versions in a set, which could hold several versions of
+/// the same report, into the state hash
+async fn migrate_legacy_reports_data(keys: &ReportStateKeys) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let exists: bool = kv.exists(&keys.legacy).await?;
+    if !exists {
+        return Ok(());
+    }
+
+    let legacy_data = redis::load_set_to_vec(&keys.legacy, &mut kv).await?;
+    let reports = merge_legacy_reports(&legacy_data);
+    save_reports_data(keys, &reports).await?;
+    kv.del::<_, ()>(&keys.legacy).await?;
+    info!("migrated {} saved reports to {}", reports.len(), keys.state);
+    Ok(())
+}
+
+/// One version of every report in the legacy set, the most complete one. Entries that
+/// don't deserialize are skipped.
+fn merge_legacy_reports(legacy_data: &[String]) -> Vec<models::ReportData> {
+    let mut reports: Vec<models::ReportData> = vec![];
+    for d in legacy_data {
+        let Ok(report) = serde_json::from_str::<models::ReportData>(d) else {
+            continue;
+        };
+
+        match reports.iter_mut().find(|r| r.id == report.id) {
+            Some(saved) if report.substate.is_some() && saved.substate.is_none() => *saved = report,
+            Some(_) => {}
+            None => reports.push(report),
+        }
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(id: &str) -> models::ReportData {
+        models::ReportData {
+            id: Some(String::from(id)),
+            disclosed: true,
+            ..Default::default()
+        }
+    }
+
+    fn page(ids: std::ops::Range<usize>) -> Vec<models::ReportData> {
+        ids.map(|id| report(&id.to_string())).collect()
+    }
+
+    #[test]
+    fn stops_at_seen_reports() {
+        let seen = vec![report("30")];
+        let mut reports = vec![];
+
+        assert_eq!(add_page(&mut reports, page(0..25), &seen, 25, 1000, 100), None);
+        assert_eq!(
+            add_page(&mut reports, page(25..50), &seen, 50, 1000, 100),
+            Some(PagingStop::ReachedSeenReports)
+        );
+        assert_eq!(reports.len(), 50);
+    }
+
+    #[test]
+    fn pages_past_changed_reports() {
+        let mut changed = report("3");
+        changed.awarded_amount = 500.0;
+        let seen = vec![report("3")];
+
+        let mut reports = vec![];
+        assert_eq!(add_page(&mut reports, vec![changed], &seen, 25, 1000, 100), None);
+    }
+
+    #[test]
+    fn stops_at_the_last_page() {
+        let mut reports = vec![];
+        assert_eq!(
+            add_page(&mut reports, page(0..10), &[], 25, 10, 100),
+            Some(PagingStop::LastPage)
+        );
+
+        // a seen report on the last page is reported as reaching the seen reports
+        let seen = vec![report("4")];
+        assert_eq!(
+            add_page(&mut vec![], page(0..10), &seen, 25, 10, 100),
+            Some(PagingStop::ReachedSeenReports)
+        );
+    }
+
+    #[test]
+    fn caps_reports_per_poll() {
+        let mut reports = vec![];
+        assert_eq!(add_page(&mut reports, page(0..25), &[], 25, 1000, 40), None);
+        assert_eq!(
+            add_page(&mut reports, page(25..50), &[], 50, 1000, 40),
+            Some(PagingStop::Capped)
+        );
+        assert_eq!(reports.len(), 50);
+    }
+
+    #[test]
+    fn skips_reports_shifted_between_pages() {
+        let mut reports = vec![];
+        add_page(&mut reports, page(0..25), &[], 25, 1000, 100);
+        // a new disclosure pushed report 24 onto the second page
+        add_page(&mut reports, page(24..49), &[], 50, 1000, 100);
+
+        assert_eq!(reports.len(), 49);
+        assert_eq!(reports.last().unwrap().id.as_deref(), Some("48"));
+    }
+
+    #[test]
+    fn prunes_reports_past_retention() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let days_ago = |days: i64| (now - chrono::Duration::days(days).num_milliseconds()) as f64;
+        let seen = vec![
+            (String::from("1"), days_ago(120)),
+            (String::from("2"), days_ago(REPORT_RETENTION_DAYS)),
+            (String::from("3"), days_ago(30)),
+            (String::from("4"), days_ago(0)),
+        ];
+
+        assert_eq!(pruned_reports(&seen, now, 100), vec!["1", "2"]);
+        assert!(pruned_reports(&seen[2..], now, 100).is_empty());
+        assert!(pruned_reports(&[], now, 100).is_empty());
+    }
+
+    #[test]
+    fn prunes_the_oldest_reports_beyond_the_limit() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let seen: Vec<(String, f64)> =
+            (0..10).map(|id| (id.to_string(), (now - 10 + id) as f64)).collect();
+
+        assert_eq!(pruned_reports(&seen, now, 7), vec!["0", "1", "2"]);
+        assert!(pruned_reports(&seen, now, 10).is_empty());
+
+        // expired reports count towards the limit
+        let mut seen = seen;
+        seen[0].1 = 0.0;
+        seen[1].1 = 0.0;
+        assert_eq!(pruned_reports(&seen, now, 9), vec!["0", "1"]);
+    }
+
+    #[test]
+    fn reports_without_a_disclosure_date_are_recent() {
+        let now = chrono::Utc::now();
+        let mut report = report("1");
+        assert!(!is_past_retention(&report, now));
+
+        report.disclosed_at = Some(String::from("yesterday"));
+        assert!(!is_past_retention(&report, now));
+
+        report.disclosed_at = Some((now - chrono::Duration::days(10)).to_rfc3339());
+        assert!(!is_past_retention(&report, now));
+
+        let past_retention = now - chrono::Duration::days(REPORT_RETENTION_DAYS + 1);
+        report.disclosed_at = Some(past_retention.to_rfc3339());
+        assert!(is_past_retention(&report, now));
+    }
+
+    #[test]
+    fn merges_legacy_reports() {
+        let mut triaged = report("1");
+        triaged.substate = Some(String::from("resolved"));
+        let legacy_data: Vec<String> = [report("1"), triaged, report("2"), report("1")]
+            .iter()
+            .map(|report| serde_json::to_string(report).unwrap())
+            .chain([String::from("{not json")])
+            .collect();
+
+        let reports = merge_legacy_reports(&legacy_data);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].id.as_deref(), Some("1"));
+        assert_eq!(reports[0].substate.as_deref(), Some("resolved"));
+        assert_eq!(reports[1].id.as_deref(), Some("2"));
+    }
+}
@@ -1,34 +1,56 @@
 use std::collections::HashMap;
 
-use super::PollConfiguration;
-extern crate cronjob;
+use super::{informative_reports, PollConfiguration, TeamErrors, WATCHLIST_SCOPE};
+use crate::metrics;
 use chrono;
 use graphql_client::GraphQLQuery;
 use security_api::hackerone::{self as hackerone, HackerOneClient};
 use security_api::models::{self as models};
-use security_api::redis::{self, redis::AsyncCommands};
+use security_api::redis;
+use security_api::reputation_history;
 
 pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     debug!("running poll");
 
     let mut kv = redis::get_connection().get().await?;
-    let previous_reputation_save = get_saved_reputation_data().await?;
+    let include_team_handle =
+        config.team_handles.len() != 1 || config.is_watching_other_programs();
+
+    let mut errors = TeamErrors::default();
+    for team_handle in config.team_scopes() {
+        let result = async {
+            let programs = match team_handle {
+                Some(WATCHLIST_SCOPE) => watched_programs(config).await?,
+                Some(team_handle) => vec![team_handle.to_owned()],
+                // Get reputation data from all programs
+                None => {
+                    let programs_key = redis::key(models::redis_keys::PROGRAMS);
+                    redis::load_set_to_vec(&programs_key, &mut kv).await?
+                }
+            };
+
+            run_program_poll(config, team_handle, programs, include_team_handle).await
+        };
 
-    let mut programs = vec![];
-    if let Some(team_handle) = &config.team_handle {
-        programs.push(team_handle.to_owned());
-    } else {
-        // Get reputation data from all programs
-        let mut _programs =
-            redis::load_set_to_vec(models::redis_keys::PROGRAMS, &mut kv).await?;
-        programs.append(&mut _programs);
+        errors.record(team_handle, result.await);
     }
 
+    errors.into_result()
+}
+
+async fn run_program_poll(
+    config: &PollConfiguration,
+    team_handle: Option<&str>,
+    programs: Vec<String>,
+    include_team_handle: bool,
+) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let previous_reputation_save = get_saved_reputation_data(team_handle).await?;
+    let last_data_key = redis::team_key(models::redis_keys::REPUTATION_QUEUE_LAST_DATA, team_handle);
+
     debug!("getting rep data for {} programs", programs.len());
 
     let mut leaderboard = vec![];
-    let single_program = programs.len() == 1;
-
     for program in programs {
         let mut program_leaderboard = hackerone_get_leaderboard(&program, &config.hackerone, true, None, None).await?;
         leaderboard.append(&mut program_leaderboard);
@@ -38,7 +60,7 @@ pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
     // First Run
     if previous_reputation_save.is_none() {
         redis::save_vec_to_set(
-            models::redis_keys::REPUTATION_QUEUE_LAST_DATA,
+            &last_data_key,
             leaderboard,
             true,
             &mut kv,
@@ -97,35 +119,95 @@ pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
         }
     }
 
-    debug!("reputation poll event: changed len: {}", changed.len());
-    if !changed.is_empty() {
+    // the history, the leaderboard and the announcement are written in one transaction, so
+    // a failure leaves none of them written and the whole change is picked up again next run
+    let mut pipe = redis::redis::pipe();
+    pipe.atomic();
+    record_reputation_history(&changed, &mut pipe)?;
+    redis::pipe_replace_set(&mut pipe, &last_data_key, &leaderboard)?;
+
+    let mut announced = changed.clone();
+    retain_watched(config, &mut announced);
+    debug!("reputation poll event: changed len: {}", announced.len());
+    metrics::record_changes("reputation", announced.len());
+    if !announced.is_empty() {
         let mut queue_item = models::RepDataQueueItem {
             id: None,
-            diff: changed.clone(),
+            diff: announced.clone(),
             created_at: chrono::Utc::now().naive_utc(),
-            include_team_handle: !single_program,
+            include_team_handle,
         };
 
         queue_item.create_id();
-        let queue_item_encoded = serde_json::to_string(&queue_item)?;
-        kv.publish::<&str, std::string::String, i32>(
-            models::redis_keys::REPUTATION_QUEUE_PUBSUB,
-            queue_item_encoded,
-        ).await?;
-        add_queue_item_to_backlog(&queue_item).await?;
+        redis::pipe_publish_to_stream(
+            &mut pipe,
+            &redis::key(models::redis_keys::REPUTATION_QUEUE_STREAM),
+            &queue_item,
+        )?;
     }
 
-    redis::save_vec_to_set(
-        models::redis_keys::REPUTATION_QUEUE_LAST_DATA,
-        leaderboard,
-        true,
-        &mut kv,
-    ).await?;
+    pipe.query_async::<()>(&mut kv).await?;
 
-    info!("ran poll, {} changes", changed.len());
+    info!("ran poll for {}, {} changes", team_handle.unwrap_or("all programs"), announced.len());
     Ok(())
 }
 
+/// Keeps changes of watched researchers, including researchers that left the leaderboard
+fn retain_watched(config: &PollConfiguration, changed: &mut Vec<Vec<models::RepData>>) {
+    changed.retain(|diff| {
+        diff.iter()
+            .any(|rep| config.is_watched(&rep.user_name, &rep.user_id))
+    });
+}
+
+/// Every change is kept, including changes of researchers that aren't watched
+fn record_reputation_history(
+    changed: &[Vec<models::RepData>],
+    pipe: &mut redis::redis::Pipeline,
+) -> Result<(), anyhow::Error> {
+    let recorded_at = chrono::Utc::now().naive_utc();
+    for diff in changed {
+        let (old, new) = (&diff[0], &diff[1]);
+
+        // researchers removed from the leaderboard have no new reputation
+        let Some(team_handle) = &new.team_handle else {
+            continue;
+        };
+
+        let point = models::ReputationPoint {
+            reputation: new.reputation,
+            rank: new.rank,
+            previous_reputation: old.reputation,
+            recorded_at,
+        };
+
+        reputation_history::record(team_handle, &new.user_id, &point, pipe)?;
+    }
+
+    Ok(())
+}
+
+/// Programs the watched researchers have been thanked in, besides the tracked ones
+async fn watched_programs(config: &PollConfiguration) -> Result<Vec<String>, anyhow::Error> {
+    let mut programs: Vec<String> = vec![];
+    for username in config.watched_usernames() {
+        let thanks =
+            informative_reports::hackerone_get_user_thanks_data(username, &config.hackerone, None)
+                .await?;
+
+        for thanks in thanks {
+            if !config.is_tracked_program(&thanks.team_handle)
+                && !programs.contains(&thanks.team_handle)
+            {
+                programs.push(thanks.team_handle);
+            }
+        }
+    }
+
+    debug!("watched researchers appear in {} other programs", programs.len());
+    Ok(programs)
+}
+
 #[rustfmt::skip]
 async fn hackerone_get_leaderboard(handle: &str, client: &HackerOneClient, get_full_leaderboard: bool, previous_data: Option<Vec<models::RepData>>, next_cursor: Option<String>) -> Result<Vec<models::RepData>, anyhow::Error> {
     debug!("get reputation data {}, cursor: {}", handle, next_cursor.as_ref().unwrap_or(&String::from("")));
@@ -139,8 +221,8 @@ async fn hackerone_get_leaderboard(handle: &str, client: &HackerOneClient, get_f
     let response = client.http.post("https://hackerone.com/graphql").json(&query).send().await?;
 
     let mut result: Vec<models::RepData> = vec![];
-    if previous_data.is_some() {
-        result = previous_data.unwrap();
+    if let Some(previous_data) = previous_data {
+        result = previous_data;
     }
 
     if !response.status().is_success() {
@@ -151,6 +233,7 @@ async fn hackerone_get_leaderboard(handle: &str, client: &HackerOneClient, get_f
     trace!("{} {:?}", handle, data);
     if let Some(errors) = data.errors {
         if !errors.is_empty() {
+            metrics::record_graphql_error("TeamYearThankQuery");
             return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
         }
     }
@@ -193,10 +276,10 @@ async fn hackerone_get_leaderboard(handle: &str, client: &HackerOneClient, get_f
     Ok(result)
 }
 
-pub async fn get_saved_reputation_data() -> Result<Option<Vec<models::RepData>>, anyhow::Error> {
+pub async fn get_saved_reputation_data(team_handle: Option<&str>) -> Result<Option<Vec<models::RepData>>, anyhow::Error> {
     let mut kv = redis::get_connection().get().await?;
     let last_rep_data = redis::load_set_to_vec(
-        models::redis_keys::REPUTATION_QUEUE_LAST_DATA,
+        &redis::team_key(models::redis_keys::REPUTATION_QUEUE_LAST_DATA, team_handle),
         &mut kv,
     ).await?;
 
@@ -213,11 +296,41 @@ pub async fn get_saved_reputation_data() -> Result<Option<Vec<models::RepData>>,
     Ok(Some(data))
 }
 
-async fn add_queue_item_to_backlog(item: &models::RepDataQueueItem) -> Result<(), anyhow::Error> {
-    let mut kv = redis::get_connection().get().await?;
-    let serialized = serde_json::to_string(item)?;
-    let now = chrono::Utc::now().timestamp_millis();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polls::tests::config;
 
-    kv.zadd::<_, _, _, ()>(models::redis_keys::REPUTATION_QUEUE_BACKLOG, serialized, now).await?;
-    Ok(())
+    fn rep(user_name: &str, user_id: &str, reputation: i64) -> models::RepData {
+        models::RepData {
+            reputation,
+            user_name: String::from(user_name),
+            user_id: String::from(user_id),
+            team_handle: Some(String::from("security")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_changes_of_watched_researchers() {
+        let removed = models::RepData {
+            reputation: -1,
+            rank: -1,
+            ..Default::default()
+        };
+
+        let mut changed = vec![
+            vec![rep("hackermon", "1", 10), rep("hackermon", "1", 17)],
+            vec![rep("someone", "2", 10), rep("someone", "2", 17)],
+            vec![rep("", "", -1), rep("newcomer", "3", 7)],
+            vec![rep("leaver", "4", 50), removed],
+        ];
+
+        retain_watched(&config(&["hackermon", "3", "leaver"]), &mut changed);
+        let names: Vec<&str> = changed
+            .iter()
+            .map(|diff| diff.iter().map(|rep| rep.user_name.as_str()).max().unwrap())
+            .collect();
+        assert_eq!(names, vec!["hackermon", "newcomer", "leaver"]);
+    }
 }
@@ -0,0 +1,264 @@
+use graphql_client::GraphQLQuery;
+use security_api::hackerone::{self, HackerOneClient};
+use security_api::models;
+use security_api::redis;
+
+use super::{PollConfiguration, TeamErrors};
+use crate::metrics;
+
+pub async fn run_poll(config: &PollConfiguration) -> Result<(), anyhow::Error> {
+    debug!("running poll");
+
+    // scopes are only snapshotted for tracked programs
+    let mut errors = TeamErrors::default();
+    for team_handle in &config.team_handles {
+        let result = run_program_poll(config, team_handle).await;
+        errors.record(Some(team_handle), result);
+    }
+
+    errors.into_result()
+}
+
+async fn run_program_poll(
+    config: &PollConfiguration,
+    team_handle: &str,
+) -> Result<(), anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let last_data_key = models::redis_keys::STRUCTURED_SCOPES_POLL_LAST_DATA;
+    let previous_scopes = get_saved_scopes_data(team_handle).await?;
+    let scopes = hackerone_get_structured_scopes(team_handle, &config.hackerone, None).await?;
+
+    // First Run, a program without assets is saved as an empty scope
+    let Some(previous_scopes) = previous_scopes else {
+        redis::save_snapshot(last_data_key, Some(team_handle), &scopes, &mut kv).await?;
+        return Ok(());
+    };
+
+    let changed = diff_scopes(previous_scopes, &scopes);
+    debug!(
+        "structured scopes poll event: changed len: {}",
+        changed.len()
+    );
+    metrics::record_changes("structured_scopes", changed.len());
+    if !changed.is_empty() {
+        let mut queue_item = models::ScopeDataQueueItem {
+            id: None,
+            team_handle: String::from(team_handle),
+            diff: changed.clone(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+
+        queue_item.create_id();
+        redis::publish_to_stream(
+            &redis::key(models::redis_keys::STRUCTURED_SCOPES_QUEUE_STREAM),
+            &queue_item,
+            &mut kv,
+        )
+        .await?;
+    }
+
+    redis::save_snapshot(last_data_key, Some(team_handle), &scopes, &mut kv).await?;
+    info!("ran poll for {}, {} changes", team_handle, changed.len());
+    Ok(())
+}
+
+/// `[old, new]` pairs for every added, removed or changed asset
+fn diff_scopes(
+    previous_scopes: Vec<models::ScopeData>,
+    scopes: &[models::ScopeData],
+) -> Vec<Vec<models::ScopeData>> {
+    let mut changed = vec![];
+    for scope in scopes {
+        match previous_scopes.iter().find(|old| old.id == scope.id) {
+            None => changed.push(vec![models::ScopeData::default(), scope.clone()]),
+            Some(old) if old != scope => changed.push(vec![old.clone(), scope.clone()]),
+            _ => {}
+        }
+    }
+
+    for old in previous_scopes {
+        if !scopes.iter().any(|scope| scope.id == old.id) {
+            changed.push(vec![old, models::ScopeData::default()]);
+        }
+    }
+
+    changed
+}
+
+/// `None` before the first run, a program without assets has an empty saved scope
+async fn get_saved_scopes_data(
+    team_handle: &str,
+) -> Result<Option<Vec<models::ScopeData>>, anyhow::Error> {
+    let mut kv = redis::get_connection().get().await?;
+    let last_scopes_data = redis::load_snapshot(
+        models::redis_keys::STRUCTURED_SCOPES_POLL_LAST_DATA,
+        Some(team_handle),
+        &mut kv,
+    )
+    .await?;
+    let Some(last_scopes_data) = last_scopes_data else {
+        return Ok(None);
+    };
+
+    let mut data = vec![];
+    for d in last_scopes_data {
+        let deserialized = serde_json::from_str::<models::ScopeData>(&d)?;
+        data.push(deserialized);
+    }
+
+    Ok(Some(data))
+}
+
+async fn hackerone_get_structured_scopes(
+    handle: &str,
+    client: &HackerOneClient,
+    cursor: Option<String>,
+) -> Result<Vec<models::ScopeData>, anyhow::Error> {
+    let variables = hackerone::team_structured_scopes_query::Variables {
+        handle: String::from(handle),
+        cursor: cursor.clone(),
+    };
+
+    let query = hackerone::TeamStructuredScopesQuery::build_query(variables);
+    let response = client
+        .http
+        .post("https://hackerone.com/graphql")
+        .json(&query)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::Error::msg("HackerOne API returned bad status code"));
+    }
+
+    let data = response
+        .json::<graphql_client::Response<
+            <hackerone::TeamStructuredScopesQuery as GraphQLQuery>::ResponseData,
+        >>()
+        .await?;
+    trace!("{} {:?}", handle, data);
+    if let Some(errors) = data.errors {
+        if !errors.is_empty() {
+            metrics::record_graphql_error("TeamStructuredScopesQuery");
+            return Err(anyhow::Error::msg(errors.first().unwrap().message.clone()));
+        }
+    }
+
+    let team = data.data.and_then(|data| data.team);
+    let Some(team) = team else {
+        return Err(anyhow::Error::msg(format!("cannot fetch team {}", handle)));
+    };
+
+    let Some(structured_scopes) = team.structured_scopes else {
+        warn!("{} returned no structured scopes", handle);
+        return Ok(vec![]);
+    };
+
+    let mut result = vec![];
+    for scope in structured_scopes
+        .edges
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+    {
+        let Some(scope) = scope.node else {
+            continue;
+        };
+
+        let max_severity = scope
+            .max_severity
+            .and_then(|severity| serde_json::to_value(severity).ok())
+            .and_then(|severity| severity.as_str().map(String::from));
+
+        result.push(models::ScopeData {
+            id: scope.id,
+            team_handle: team.handle.clone(),
+            asset_identifier: scope.asset_identifier,
+            asset_type: scope.human_asset_type,
+            eligible_for_submission: scope.eligible_for_submission.unwrap_or(false),
+            eligible_for_bounty: scope.eligible_for_bounty.unwrap_or(false),
+            max_severity,
+        });
+    }
+
+    let page_info = structured_scopes.page_info;
+    if page_info.has_next_page && page_info.end_cursor.is_some() {
+        let mut next_page = Box::pin(hackerone_get_structured_scopes(
+            handle,
+            client,
+            page_info.end_cursor,
+        ))
+        .await?;
+        result.append(&mut next_page);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(id: &str, eligible_for_bounty: bool, max_severity: &str) -> models::ScopeData {
+        models::ScopeData {
+            id: String::from(id),
+            team_handle: String::from("security"),
+            asset_identifier: format!("{}.example.com", id),
+            eligible_for_submission: true,
+            eligible_for_bounty,
+            max_severity: Some(String::from(max_severity)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_scopes_have_no_diff() {
+        let scopes = vec![scope("1", true, "critical"), scope("2", false, "low")];
+        assert!(diff_scopes(scopes.clone(), &scopes).is_empty());
+        assert!(diff_scopes(vec![], &[]).is_empty());
+    }
+
+    #[test]
+    fn diffs_added_and_removed_assets() {
+        let changed = diff_scopes(vec![scope("1", true, "high")], &[scope("2", true, "high")]);
+        assert_eq!(
+            changed,
+            vec![
+                vec![models::ScopeData::default(), scope("2", true, "high")],
+                vec![scope("1", true, "high"), models::ScopeData::default()],
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_modified_assets() {
+        let previous = vec![scope("1", true, "high"), scope("2", true, "high")];
+        let changed = diff_scopes(
+            previous,
+            &[scope("1", false, "high"), scope("2", true, "low")],
+        );
+        assert_eq!(
+            changed,
+            vec![
+                vec![scope("1", true, "high"), scope("1", false, "high")],
+                vec![scope("2", true, "high"), scope("2", true, "low")],
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_against_empty_scope() {
+        // a program that had no assets on the previous run
+        let changed = diff_scopes(vec![], &[scope("1", true, "high")]);
+        assert_eq!(
+            changed,
+            vec![vec![models::ScopeData::default(), scope("1", true, "high")]]
+        );
+
+        let changed = diff_scopes(vec![scope("1", true, "high")], &[]);
+        assert_eq!(
+            changed,
+            vec![vec![scope("1", true, "high"), models::ScopeData::default()]]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! When polls run: a fixed interval or a cron expression, and a daily window polls don't run in.
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Days, NaiveTime, Utc};
+
+/// Occurrences of a cron expression looked at to find the longest gap between runs
+static CRON_INTERVAL_SAMPLES: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// Runs again this long after the previous run finished
+    Every(Duration),
+    /// Runs at every occurrence of the expression (UTC)
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// How long to wait from `now` until the next run
+    pub fn next_delay(&self, now: DateTime<Utc>) -> Duration {
+        match self {
+            Schedule::Every(interval) => *interval,
+            Schedule::Cron(schedule) => schedule
+                .after(&now)
+                .next()
+                .and_then(|next| (next - now).to_std().ok())
+                .unwrap_or(Duration::MAX),
+        }
+    }
+
+    /// The longest time between two runs, polls are unhealthy once they went a few intervals
+    /// without succeeding
+    pub fn interval(&self) -> Duration {
+        match self {
+            Schedule::Every(interval) => *interval,
+            Schedule::Cron(schedule) => {
+                let upcoming: Vec<DateTime<Utc>> =
+                    schedule.upcoming(Utc).take(CRON_INTERVAL_SAMPLES).collect();
+                upcoming
+                    .windows(2)
+                    .filter_map(|runs| (runs[1] - runs[0]).to_std().ok())
+                    .max()
+                    .unwrap_or(Duration::MAX)
+            }
+        }
+    }
+}
+
+/// Parses an interval such as `90s`, `5m`, `12h` or `1d`, anything else is read as a cron
+/// expression. Cron expressions have five fields (minute, hour, day of month, month, day of
+/// week) or six with seconds first.
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(schedule: &str) -> Result<Self, Self::Err> {
+        let schedule = schedule.trim();
+        if !schedule.contains(' ') {
+            let interval = parse_duration(schedule)?;
+            if interval.is_zero() {
+                return Err(anyhow::Error::msg("poll interval has to be longer than 0s"));
+            }
+
+            return Ok(Schedule::Every(interval));
+        }
+
+        let expression = match schedule.split_whitespace().count() {
+            5 => format!("0 {}", schedule),
+            _ => String::from(schedule),
+        };
+
+        let cron = cron::Schedule::from_str(&expression).map_err(|err| {
+            anyhow::Error::msg(format!("invalid cron expression {:?}: {}", schedule, err))
+        })?;
+        if cron.upcoming(Utc).next().is_none() {
+            return Err(anyhow::Error::msg(format!(
+                "cron expression {:?} never runs",
+                schedule
+            )));
+        }
+
+        Ok(Schedule::Cron(Box::new(cron)))
+    }
+}
+
+/// Parses a duration such as `30s`, `5m`, `12h` or `1d`
+pub fn parse_duration(duration: &str) -> Result<Duration, anyhow::Error> {
+    let duration = duration.trim();
+    let invalid = || {
+        anyhow::Error::msg(format!(
+            "invalid duration {:?}, e.g. 30s, 5m, 12h or 1d",
+            duration
+        ))
+    };
+
+    let unit_start = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = duration.split_at(unit_start);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(invalid()),
+    };
+
+    let seconds = amount.checked_mul(seconds).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// A daily window (UTC) polls don't run in, written as `22:00-06:00`. Windows can wrap
+/// around midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// When the window `at` falls into ends, `None` outside of the window
+    pub fn end_after(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let time = at.time();
+        let inside = if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        };
+
+        if !inside {
+            return None;
+        }
+
+        let mut end = at.date_naive().and_time(self.end).and_utc();
+        if end <= at {
+            end = end + Days::new(1);
+        }
+
+        Some(end)
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = anyhow::Error;
+
+    fn from_str(window: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow::Error::msg(format!(
+                "invalid quiet hours {:?}, e.g. 22:00-06:00",
+                window
+            ))
+        };
+        let (start, end) = window.trim().split_once('-').ok_or_else(invalid)?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?;
+
+        Ok(QuietHours { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 10, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_intervals() {
+        let schedule: Schedule = "90s".parse().unwrap();
+        assert!(
+            matches!(schedule, Schedule::Every(interval) if interval == Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_duration("12h").unwrap(),
+            Duration::from_secs(12 * 60 * 60)
+        );
+        assert_eq!(
+            parse_duration(" 1d ").unwrap(),
+            Duration::from_secs(24 * 60 * 60)
+        );
+
+        assert!("0m".parse::<Schedule>().is_err());
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 2)).is_err());
+    }
+
+    #[test]
+    fn parses_cron_expressions() {
+        let schedule: Schedule = "*/15 * * * *".parse().unwrap();
+        assert!(matches!(schedule, Schedule::Cron(_)));
+        assert_eq!(schedule.interval(), Duration::from_secs(15 * 60));
+
+        let schedule: Schedule = "30 0 9 * * Mon-Fri".parse().unwrap();
+        assert!(matches!(schedule, Schedule::Cron(_)));
+
+        assert!("* * *".parse::<Schedule>().is_err());
+        assert!("61 * * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn waits_for_the_next_cron_run() {
+        let schedule: Schedule = "0 * * * *".parse().unwrap();
+        assert_eq!(
+            schedule.next_delay(at(10, 20)),
+            Duration::from_secs(40 * 60)
+        );
+        assert_eq!(schedule.next_delay(at(10, 0)), Duration::from_secs(60 * 60));
+
+        let schedule = Schedule::Every(Duration::from_secs(300));
+        assert_eq!(schedule.next_delay(at(10, 20)), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let quiet_hours: QuietHours = "09:00-17:00".parse().unwrap();
+        assert_eq!(quiet_hours.end_after(at(12, 0)), Some(at(17, 0)));
+        assert_eq!(quiet_hours.end_after(at(17, 0)), None);
+        assert_eq!(quiet_hours.end_after(at(8, 59)), None);
+
+        assert!("22:00".parse::<QuietHours>().is_err());
+        assert!("22:00-25:00".parse::<QuietHours>().is_err());
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        let quiet_hours: QuietHours = "22:00-06:00".parse().unwrap();
+        let next_morning = Utc.with_ymd_and_hms(2024, 5, 11, 6, 0, 0).unwrap();
+
+        assert_eq!(quiet_hours.end_after(at(23, 30)), Some(next_morning));
+        assert_eq!(quiet_hours.end_after(at(22, 0)), Some(next_morning));
+        assert_eq!(quiet_hours.end_after(at(3, 0)), Some(at(6, 0)));
+        assert_eq!(quiet_hours.end_after(at(6, 0)), None);
+        assert_eq!(quiet_hours.end_after(at(12, 0)), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! Runs every poll on its schedule. Failed runs are retried with exponential backoff, and a
+//! poll that keeps failing opens its circuit: it is announced as degraded and only tried
+//! again after a cooldown, until a run succeeds and it is announced as recovered. Panics
+//! count as failures, a malformed HackerOne response doesn't take the poller down.
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::FutureExt;
+use rand::Rng;
+use security_api::health;
+use security_api::models::{self, PollStatus};
+use security_api::redis;
+
+use crate::metrics;
+use crate::polls;
+use crate::schedule::{QuietHours, Schedule};
+
+/// First retry after a failed run, doubled on every further failure
+static RETRY_DELAY: Duration = Duration::from_secs(60);
+static MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 30);
+/// How long a poll with an open circuit waits between tries
+static CIRCUIT_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    /// Failures in a row after which a poll's circuit opens
+    pub max_consecutive_failures: u32,
+    /// Longest random delay added to every scheduled run
+    pub jitter: Duration,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl Scheduler {
+    /// Runs `run` on `schedule`, never returns. Interval schedules run right away, cron
+    /// schedules wait for their first occurrence.
+    pub async fn run<F, Fut>(&self, poll: &str, schedule: &Schedule, mut run: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), anyhow::Error>>,
+    {
+        polls::register(poll, schedule.interval()).await;
+        metrics::set_circuit_open(poll, false);
+
+        let mut consecutive_failures = 0;
+        let mut circuit_open = false;
+        let mut delay = match schedule {
+            Schedule::Every(_) => Duration::ZERO,
+            Schedule::Cron(_) => schedule
+                .next_delay(Utc::now())
+                .saturating_add(self.jitter()),
+        };
+
+        loop {
+            tokio::time::sleep(delay).await;
+            self.wait_out_quiet_hours(poll).await;
+
+            delay = match polls::run(poll, catch_panic(run())).await {
+                Ok(()) => {
+                    if circuit_open {
+                        info!(
+                            "{}: recovered after {} failures",
+                            poll, consecutive_failures
+                        );
+                        metrics::set_circuit_open(poll, false);
+                        announce(poll, PollStatus::Recovered, consecutive_failures, None).await;
+                    }
+
+                    consecutive_failures = 0;
+                    circuit_open = false;
+                    schedule
+                        .next_delay(Utc::now())
+                        .saturating_add(self.jitter())
+                }
+                Err(err) => {
+                    consecutive_failures += 1;
+                    error!(
+                        "{}: {:#} ({} failures in a row)",
+                        poll, err, consecutive_failures
+                    );
+
+                    if !circuit_open && consecutive_failures >= self.max_consecutive_failures {
+                        warn!("{}: degraded, retrying every {:?}", poll, CIRCUIT_COOLDOWN);
+                        circuit_open = true;
+                        metrics::set_circuit_open(poll, true);
+                        let error = Some(format!("{:#}", err));
+                        announce(poll, PollStatus::Degraded, consecutive_failures, error).await;
+                    }
+
+                    if circuit_open {
+                        with_jitter(CIRCUIT_COOLDOWN)
+                    } else {
+                        with_jitter(retry_delay(consecutive_failures))
+                    }
+                }
+            };
+        }
+    }
+
+    fn jitter(&self) -> Duration {
+        self.jitter.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// Sleeps until the quiet hours are over if they started
+    async fn wait_out_quiet_hours(&self, poll: &str) {
+        let now = Utc::now();
+        let end = self
+            .quiet_hours
+            .and_then(|quiet_hours| quiet_hours.end_after(now));
+        let Some(end) = end else {
+            return;
+        };
+
+        info!("{}: postponed until the quiet hours end at {}", poll, end);
+        health::snooze(poll, end).await;
+        let remaining = (end - now).to_std().unwrap_or_default();
+        tokio::time::sleep(remaining + self.jitter()).await;
+    }
+}
+
+/// Turns a panic while running `run` into an error
+async fn catch_panic(
+    run: impl Future<Output = Result<(), anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    match AssertUnwindSafe(run).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => Err(anyhow::Error::msg(format!(
+            "panicked: {}",
+            panic_message(&panic)
+        ))),
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn retry_delay(consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+    (RETRY_DELAY * 2u32.pow(exponent)).min(MAX_RETRY_DELAY)
+}
+
+/// Somewhere between half and all of `delay`, so polls failing together don't retry together
+fn with_jitter(delay: Duration) -> Duration {
+    let half = delay / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+/// Queues a notification about the poll's status. Failures are only logged, Redis being
+/// unavailable may be why the poll is failing in the first place.
+async fn announce(
+    poll: &str,
+    status: PollStatus,
+    consecutive_failures: u32,
+    error: Option<String>,
+) {
+    let mut queue_item = models::PollStatusQueueItem {
+        id: None,
+        poll: String::from(poll),
+        status,
+        consecutive_failures,
+        error,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+
+    queue_item.create_id();
+    let result = async {
+        let mut kv = redis::get_connection().get().await?;
+        let stream = redis::key(models::redis_keys::POLL_STATUS_QUEUE_STREAM);
+        redis::publish_to_stream(&stream, &queue_item, &mut kv).await
+    };
+
+    if let Err(err) = result.await {
+        error!("{}: failed to announce {:?} status: {}", poll, status, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn panics_are_failures() {
+        let result = catch_panic(async { panic!("missing team") }).await;
+        assert_eq!(result.unwrap_err().to_string(), "panicked: missing team");
+
+        let result = catch_panic(async { Err(anyhow::Error::msg("bad status code")) }).await;
+        assert_eq!(result.unwrap_err().to_string(), "bad status code");
+        assert!(catch_panic(async { Ok(()) }).await.is_ok());
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(retry_delay(1), RETRY_DELAY);
+        assert_eq!(retry_delay(3), RETRY_DELAY * 4);
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+
+        let delay = with_jitter(MAX_RETRY_DELAY);
+        assert!(delay >= MAX_RETRY_DELAY / 2 && delay <= MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn jitter_is_bounded() {
+        let scheduler = Scheduler {
+            max_consecutive_failures: 5,
+            jitter: Duration::from_secs(30),
+            quiet_hours: None,
+        };
+        for _ in 0..100 {
+            assert!(scheduler.jitter() <= scheduler.jitter);
+        }
+
+        let scheduler = Scheduler {
+            jitter: Duration::ZERO,
+            ..scheduler
+        };
+        assert_eq!(scheduler.jitter(), Duration::ZERO);
+    }
+}